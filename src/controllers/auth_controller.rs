@@ -3,11 +3,12 @@ use actix_web::{
 };
 use super::super::helpers::http_helpers;
 use serde::Serialize;
-use super::super::html_modal::html_modal;
+use super::super::html_modal::template::Template;
 use uuid::Uuid;
 use std::{
     fs::read_to_string,
-    env::current_dir
+    env::current_dir,
+    sync::LazyLock
 };
 // use std::time::{Duration, SystemTime};
 
@@ -27,10 +28,18 @@ struct User {
     test_f64: f64
 }
 
+/// The auth page, read and compiled on the first request and rendered from then on.
+static AUTH_TEMPLATE: LazyLock<Result<Template, String>> = LazyLock::new(|| {
+    let html = read_to_string("web/auth/auth.html").map_err(|e| {
+        format!("Failed to read web/auth/auth.html: {}\nCurrent dir: {}", e, current_dir().unwrap().to_str().unwrap())
+    })?;
+
+    Template::compile_named("auth.html", &html).map_err(|e| format!("Failed to compile web/auth/auth.html: {}", e))
+});
+
 pub async fn auth() -> impl Responder {
-    let read = read_to_string("web/auth/auth.html");
-    match read {
-        Ok(html) => {
+    match &*AUTH_TEMPLATE {
+        Ok(template) => {
                 let user = User {
                 id: Uuid::new_v4().to_string(),
                 name: String::from("Test Name"),
@@ -88,7 +97,7 @@ pub async fn auth() -> impl Responder {
 
             // let now = SystemTime::now();
             
-            let result = html_modal::process_string(&html, &user);
+            let result = template.render(&user);
 
             // match now.elapsed() {
             //     Ok(elapsed) => {
@@ -100,7 +109,8 @@ pub async fn auth() -> impl Responder {
             HttpResponse::Ok().body(result)
         }
         Err(e) => {
-            HttpResponse::InternalServerError().body(format!("{}\nCurrent dir: {}", e, current_dir().unwrap().to_str().unwrap()))
+            eprintln!("{}", e);
+            HttpResponse::InternalServerError().body("Internal server error")
         }
     }
 }
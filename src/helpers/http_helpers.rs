@@ -4,16 +4,16 @@ pub fn get_header_value(req: HttpRequest, key: impl AsHeaderName) -> String {
     // Gets the value of a header as a String. Returns empty is header does not exist.
     let header = req.headers().get(key);
     
-    if header == None {
+    if header.is_none() {
         return String::new();
     }
 
     match header.unwrap().to_str() {
         Ok(value) => {
-            return String::from(value);
+            String::from(value)
         }
         Err(_) => {
-            return String::new();
+            String::new()
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,630 @@
+use super::escape::{AttrValue, CssState, Escape, JsState, UrlPart};
+
+/// Attributes whose values are URLs.
+const URL_ATTRS: [&str; 12] = [
+    "href",
+    "src",
+    "action",
+    "formaction",
+    "cite",
+    "data",
+    "poster",
+    "background",
+    "longdesc",
+    "usemap",
+    "manifest",
+    "xlink:href",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Text,
+    TagName,
+    EndTag,
+    InTag,
+    AttrName,
+    AfterAttrName,
+    BeforeAttrValue,
+    AttrValue { quote: Option<u8>, value: AttrValue },
+    Script(JsState),
+    Style(CssState),
+    Comment,
+}
+
+/// Tracks which part of an HTML document the template text has reached, so values written at that point can be
+/// escaped for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlContext {
+    state: State,
+    tag: String,
+    attr: String,
+    js_escaped: bool,
+    /// Whether a `/` in JavaScript code starts a regular expression here rather than dividing, which depends on
+    /// the last character of code other than whitespace.
+    js_regex_next: bool,
+    /// Whether a regular expression has reached a `[...]` class, where `/` does not end it.
+    js_regex_class: bool,
+    /// For each `${` substitution of a template literal being written, how many braces it has open.
+    js_substitutions: Vec<usize>,
+}
+
+impl HtmlContext {
+    pub fn new() -> HtmlContext {
+        HtmlContext {
+            state: State::Text,
+            tag: String::new(),
+            attr: String::new(),
+            js_escaped: false,
+            js_regex_next: true,
+            js_regex_class: false,
+            js_substitutions: vec![],
+        }
+    }
+
+    /// Whether the context is in HTML text, outside of any tag, comment, script or style.
+    pub fn is_text(&self) -> bool {
+        *self == HtmlContext::new()
+    }
+
+    /// The escaping to use for a value written at the current position.
+    pub fn escape(&self) -> Escape {
+        match self.state {
+            State::Text | State::Comment => Escape::Html,
+            State::Script(js) => Escape::Script(js),
+            State::Style(css) => Escape::Style(css),
+            State::AttrValue { quote, value } => Escape::Attr {
+                quoted: quote.is_some(),
+                value,
+            },
+            State::BeforeAttrValue => Escape::Attr {
+                quoted: false,
+                value: attr_value(&self.attr),
+            },
+            State::TagName
+            | State::EndTag
+            | State::InTag
+            | State::AttrName
+            | State::AfterAttrName => Escape::Attr {
+                quoted: false,
+                value: AttrValue::Text,
+            },
+        }
+    }
+
+    /// Moves the context past a value that was written at the current position.
+    pub fn after_value(&mut self) {
+        match self.state {
+            // a value written as code is a literal, so a `/` after it divides.
+            State::Script(JsState::Code)
+            | State::AttrValue {
+                value: AttrValue::Js(JsState::Code),
+                ..
+            } => self.js_regex_next = false,
+            // a URL stays at its start until template text is written, so values written one after another
+            // cannot build a scheme between them.
+            State::BeforeAttrValue => {
+                self.state = State::AttrValue {
+                    quote: None,
+                    value: attr_value(&self.attr),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves the context past a run of template text.
+    pub fn advance(&mut self, text: &[u8]) {
+        let mut i = 0;
+
+        while i < text.len() {
+            let ch = text[i];
+            let rest = &text[i..];
+
+            self.state = match self.state {
+                State::Text => {
+                    if starts_with_ignore_case(rest, b"<!--") {
+                        i += 3;
+                        State::Comment
+                    } else if ch == b'<' && rest.get(1) == Some(&b'/') {
+                        i += 1;
+                        State::EndTag
+                    } else if ch == b'<' && rest.get(1).is_some_and(u8::is_ascii_alphabetic) {
+                        self.tag.clear();
+                        State::TagName
+                    } else {
+                        State::Text
+                    }
+                }
+                State::Comment => {
+                    if rest.starts_with(b"-->") {
+                        i += 2;
+                        self.text()
+                    } else {
+                        State::Comment
+                    }
+                }
+                State::EndTag => {
+                    if ch == b'>' {
+                        self.text()
+                    } else {
+                        State::EndTag
+                    }
+                }
+                State::TagName => {
+                    if ch.is_ascii_whitespace() || ch == b'/' {
+                        State::InTag
+                    } else if ch == b'>' {
+                        self.element_content()
+                    } else {
+                        self.tag.push(ch.to_ascii_lowercase() as char);
+                        State::TagName
+                    }
+                }
+                State::InTag => {
+                    if ch == b'>' {
+                        self.element_content()
+                    } else if ch.is_ascii_whitespace() || ch == b'/' {
+                        State::InTag
+                    } else {
+                        self.attr.clear();
+                        self.attr.push(ch.to_ascii_lowercase() as char);
+                        State::AttrName
+                    }
+                }
+                State::AttrName => {
+                    if ch == b'=' {
+                        State::BeforeAttrValue
+                    } else if ch == b'>' {
+                        self.element_content()
+                    } else if ch.is_ascii_whitespace() {
+                        State::AfterAttrName
+                    } else {
+                        self.attr.push(ch.to_ascii_lowercase() as char);
+                        State::AttrName
+                    }
+                }
+                State::AfterAttrName => {
+                    if ch == b'=' {
+                        State::BeforeAttrValue
+                    } else if ch == b'>' {
+                        self.element_content()
+                    } else if ch.is_ascii_whitespace() {
+                        State::AfterAttrName
+                    } else {
+                        self.attr.clear();
+                        self.attr.push(ch.to_ascii_lowercase() as char);
+                        State::AttrName
+                    }
+                }
+                State::BeforeAttrValue => {
+                    let value = attr_value(&self.attr);
+
+                    if ch == b'"' || ch == b'\'' {
+                        self.reset_js();
+                        State::AttrValue {
+                            quote: Some(ch),
+                            value,
+                        }
+                    } else if ch == b'>' {
+                        self.element_content()
+                    } else if ch.is_ascii_whitespace() {
+                        State::BeforeAttrValue
+                    } else {
+                        self.reset_js();
+                        let (value, len) = self.attr_value_char(value, rest);
+                        i += len;
+
+                        State::AttrValue { quote: None, value }
+                    }
+                }
+                State::AttrValue { quote, value } => match quote {
+                    Some(quote) if ch == quote => self.end_attr_value(),
+                    None if ch.is_ascii_whitespace() => self.end_attr_value(),
+                    None if ch == b'>' => self.element_content(),
+                    _ => {
+                        let (value, len) = self.attr_value_char(value, rest);
+                        i += len;
+
+                        State::AttrValue { quote, value }
+                    }
+                },
+                State::Script(js) => {
+                    if starts_with_ignore_case(rest, b"</script") {
+                        i += 1;
+                        State::EndTag
+                    } else {
+                        let (js, len) = self.js_char(js, rest);
+                        i += len;
+
+                        State::Script(js)
+                    }
+                }
+                State::Style(css) => {
+                    if starts_with_ignore_case(rest, b"</style") {
+                        i += 1;
+                        State::EndTag
+                    } else {
+                        let (css, len) = css_char(css, rest);
+                        i += len;
+
+                        State::Style(css)
+                    }
+                }
+            };
+
+            i += 1;
+        }
+    }
+
+    /// The state after the `>` of an opening tag.
+    fn element_content(&mut self) -> State {
+        self.attr.clear();
+        self.reset_js();
+
+        match self.tag.as_str() {
+            "script" => State::Script(JsState::Code),
+            "style" => State::Style(CssState::Code),
+            _ => self.text(),
+        }
+    }
+
+    /// The text state, forgetting the tag and attribute it came from so contexts that reach text compare equal.
+    fn text(&mut self) -> State {
+        self.tag.clear();
+        self.attr.clear();
+        self.reset_js();
+        State::Text
+    }
+
+    /// The state after the end of an attribute value.
+    fn end_attr_value(&mut self) -> State {
+        self.attr.clear();
+        self.reset_js();
+        State::InTag
+    }
+
+    fn reset_js(&mut self) {
+        self.js_escaped = false;
+        self.js_regex_next = true;
+        self.js_regex_class = false;
+        self.js_substitutions.clear();
+    }
+
+    /// Moves an attribute value past the character at the start of `rest`, returning the new value along with
+    /// how many characters after it were read too.
+    fn attr_value_char(&mut self, value: AttrValue, rest: &[u8]) -> (AttrValue, usize) {
+        let ch = rest[0];
+        let value = match value {
+            AttrValue::Text => AttrValue::Text,
+            AttrValue::Html => AttrValue::Html,
+            AttrValue::Url(_) if ch == b'?' || ch == b'#' => AttrValue::Url(UrlPart::Query),
+            // browsers drop leading whitespace and control characters before reading the scheme.
+            AttrValue::Url(UrlPart::Start) if ch.is_ascii_whitespace() || ch.is_ascii_control() => {
+                AttrValue::Url(UrlPart::Start)
+            }
+            AttrValue::Url(UrlPart::Query) => AttrValue::Url(UrlPart::Query),
+            AttrValue::Url(_) => AttrValue::Url(UrlPart::Path),
+            AttrValue::Js(js) => {
+                let (js, len) = self.js_char(js, rest);
+                return (AttrValue::Js(js), len);
+            }
+            AttrValue::Css(css) => {
+                let (css, len) = css_char(css, rest);
+                return (AttrValue::Css(css), len);
+            }
+        };
+
+        (value, 0)
+    }
+
+    /// Moves JavaScript past the character at the start of `rest`, returning the new state along with how many
+    /// characters after it were read too, for pairs such as `//`, `*/` and `${`. A string other than a template
+    /// literal cannot span lines, so a line break returns to code if the context lost track of a quote.
+    fn js_char(&mut self, js: JsState, rest: &[u8]) -> (JsState, usize) {
+        let ch = rest[0];
+        let next = rest.get(1).copied();
+
+        match js {
+            JsState::Code => match (ch, next) {
+                (b'/', Some(b'/')) => (JsState::LineComment, 1),
+                (b'/', Some(b'*')) => (JsState::BlockComment, 1),
+                (b'/', _) if self.js_regex_next => {
+                    self.js_regex_class = false;
+                    (JsState::Regex, 0)
+                }
+                (b'"' | b'\'' | b'`', _) => (JsState::String(ch), 0),
+                (b'}', _) if self.js_substitutions.last() == Some(&0) => {
+                    self.js_substitutions.pop();
+                    (JsState::String(b'`'), 0)
+                }
+                _ => {
+                    if let Some(open) = self.js_substitutions.last_mut() {
+                        match ch {
+                            b'{' => *open += 1,
+                            b'}' => *open -= 1,
+                            _ => {}
+                        }
+                    }
+
+                    if !ch.is_ascii_whitespace() {
+                        self.js_regex_next = starts_regex(ch);
+                    }
+
+                    (JsState::Code, 0)
+                }
+            },
+            JsState::LineComment if ch == b'\n' => (JsState::Code, 0),
+            JsState::LineComment => (js, 0),
+            JsState::BlockComment if ch == b'*' && next == Some(b'/') => (JsState::Code, 1),
+            JsState::BlockComment => (js, 0),
+            JsState::Regex | JsState::String(_) if self.js_escaped => {
+                self.js_escaped = false;
+                (js, 0)
+            }
+            JsState::Regex | JsState::String(_) if ch == b'\\' => {
+                self.js_escaped = true;
+                (js, 0)
+            }
+            JsState::Regex => match ch {
+                b'[' => {
+                    self.js_regex_class = true;
+                    (js, 0)
+                }
+                b']' => {
+                    self.js_regex_class = false;
+                    (js, 0)
+                }
+                b'/' if !self.js_regex_class => {
+                    self.js_regex_next = false;
+                    (JsState::Code, 0)
+                }
+                b'\n' => (JsState::Code, 0),
+                _ => (js, 0),
+            },
+            JsState::String(b'`') if ch == b'$' && next == Some(b'{') => {
+                self.js_substitutions.push(0);
+                self.js_regex_next = true;
+                (JsState::Code, 1)
+            }
+            JsState::String(quote) if ch == quote => {
+                self.js_regex_next = false;
+                (JsState::Code, 0)
+            }
+            JsState::String(quote) if ch == b'\n' && quote != b'`' => (JsState::Code, 0),
+            JsState::String(_) => (js, 0),
+        }
+    }
+}
+
+impl Default for HtmlContext {
+    fn default() -> Self {
+        HtmlContext::new()
+    }
+}
+
+fn attr_value(attr: &str) -> AttrValue {
+    if attr.starts_with("on") {
+        AttrValue::Js(JsState::Code)
+    } else if attr == "style" {
+        AttrValue::Css(CssState::Code)
+    } else if attr == "srcdoc" {
+        AttrValue::Html
+    } else if URL_ATTRS.contains(&attr) {
+        AttrValue::Url(UrlPart::Start)
+    } else {
+        AttrValue::Text
+    }
+}
+
+/// Whether a `/` after the supplied character starts a regular expression rather than dividing. A `/` after a
+/// value, such as a name, number, `)` or `]`, divides.
+fn starts_regex(last: u8) -> bool {
+    last == 0 || b"(,=:[!&|?{};+-*%<>~^".contains(&last)
+}
+
+/// Moves CSS past the character at the start of `rest`, returning the new state along with how many characters
+/// after it were read too. As in JavaScript, a line break ends a string the context lost track of.
+fn css_char(css: CssState, rest: &[u8]) -> (CssState, usize) {
+    let ch = rest[0];
+    let next = rest.get(1).copied();
+
+    match css {
+        CssState::Code if ch == b'/' && next == Some(b'*') => (CssState::Comment, 1),
+        CssState::Code if ch == b'"' || ch == b'\'' => (CssState::String(ch), 0),
+        CssState::String(quote) if ch == quote || ch == b'\n' => (CssState::Code, 0),
+        CssState::Comment if ch == b'*' && next == Some(b'/') => (CssState::Code, 1),
+        _ => (css, 0),
+    }
+}
+
+fn starts_with_ignore_case(text: &[u8], prefix: &[u8]) -> bool {
+    text.len() >= prefix.len() && text[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escape_after(text: &str) -> Escape {
+        let mut context = HtmlContext::new();
+        context.advance(text.as_bytes());
+        context.escape()
+    }
+
+    #[test]
+    fn test_context_text() {
+        assert_eq!(escape_after("<p class=\"a\">Hello "), Escape::Html);
+        assert_eq!(escape_after("<!-- <script> -->"), Escape::Html);
+    }
+
+    #[test]
+    fn test_context_attr() {
+        assert_eq!(
+            escape_after("<input value=\""),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Text
+            }
+        );
+        assert_eq!(
+            escape_after("<input value="),
+            Escape::Attr {
+                quoted: false,
+                value: AttrValue::Text
+            }
+        );
+    }
+
+    #[test]
+    fn test_context_url() {
+        assert_eq!(
+            escape_after("<a href=\""),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Url(UrlPart::Start)
+            }
+        );
+        assert_eq!(
+            escape_after("<a href=\" \n\t"),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Url(UrlPart::Start)
+            }
+        );
+        assert_eq!(
+            escape_after("<a HREF='/users/"),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Url(UrlPart::Path)
+            }
+        );
+        assert_eq!(
+            escape_after("<a href=\"/login?next="),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Url(UrlPart::Query)
+            }
+        );
+    }
+
+    #[test]
+    fn test_context_after_value() {
+        let mut context = HtmlContext::new();
+        context.advance(b"<a href=\"");
+        context.after_value();
+
+        assert_eq!(
+            context.escape(),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Url(UrlPart::Start)
+            }
+        );
+
+        context.advance(b"/");
+
+        assert_eq!(
+            context.escape(),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Url(UrlPart::Path)
+            }
+        );
+    }
+
+    #[test]
+    fn test_context_script() {
+        assert_eq!(
+            escape_after("<script>var a = "),
+            Escape::Script(JsState::Code)
+        );
+        assert_eq!(
+            escape_after("<script>var a = 'it\\'s "),
+            Escape::Script(JsState::String(b'\''))
+        );
+        assert_eq!(escape_after("<script>var a = 1;</script> "), Escape::Html);
+        assert_eq!(
+            escape_after("<button onclick=\"go("),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Js(JsState::Code)
+            }
+        );
+    }
+
+    #[test]
+    fn test_context_script_comments() {
+        assert_eq!(
+            escape_after("<script>// don't\nvar a = "),
+            Escape::Script(JsState::Code)
+        );
+        assert_eq!(
+            escape_after("<script>/* it's \"quoted\" */ var a = "),
+            Escape::Script(JsState::Code)
+        );
+        assert_eq!(
+            escape_after("<script>// don't "),
+            Escape::Script(JsState::LineComment)
+        );
+        assert_eq!(
+            escape_after("<button onclick=\"/* don't */ go("),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Js(JsState::Code)
+            }
+        );
+    }
+
+    #[test]
+    fn test_context_script_regex() {
+        assert_eq!(
+            escape_after("<script>var re = /'[/\"]/g; var a = "),
+            Escape::Script(JsState::Code)
+        );
+        assert_eq!(
+            escape_after("<script>var a = b / 2, c = '"),
+            Escape::Script(JsState::String(b'\''))
+        );
+        assert_eq!(
+            escape_after("<script>var re = /a"),
+            Escape::Script(JsState::Regex)
+        );
+    }
+
+    #[test]
+    fn test_context_script_template_literal() {
+        assert_eq!(
+            escape_after("<script>var a = `${ "),
+            Escape::Script(JsState::Code)
+        );
+        assert_eq!(
+            escape_after("<script>var a = `${ {b: 1}.b } "),
+            Escape::Script(JsState::String(b'`'))
+        );
+        assert_eq!(
+            escape_after("<script>var a = 'lost\nvar b = "),
+            Escape::Script(JsState::Code)
+        );
+    }
+
+    #[test]
+    fn test_context_style() {
+        assert_eq!(
+            escape_after("<style>p { color: "),
+            Escape::Style(CssState::Code)
+        );
+        assert_eq!(
+            escape_after("<div style=\"color: "),
+            Escape::Attr {
+                quoted: true,
+                value: AttrValue::Css(CssState::Code)
+            }
+        );
+        assert_eq!(escape_after("<style>p {}</style>"), Escape::Html);
+        assert_eq!(
+            escape_after("<style>/* don't */ p { color: "),
+            Escape::Style(CssState::Code)
+        );
+        assert_eq!(escape_after("<style>/* "), Escape::Style(CssState::Comment));
+    }
+}
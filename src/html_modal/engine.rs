@@ -0,0 +1,567 @@
+use super::error::{RenderError, TemplateError, TemplateErrorKind};
+use super::filters::{self, TemplateFilter};
+use super::functions::{self, TemplateFunction};
+use super::render::Renderer;
+use super::template::Template;
+use super::tokens::{CustomToken, TokenHandler};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::{Component, PathBuf};
+use std::sync::Arc;
+
+/// The rules used to decide whether a condition's value counts as true.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Truthiness {
+    /// Only a true bool counts as true.
+    #[default]
+    Strict,
+    /// JavaScript like rules. False, null, zero, and empty strings, arrays and objects count as false, everything
+    /// else counts as true.
+    #[allow(dead_code)]
+    Loose,
+}
+
+impl Truthiness {
+    pub fn is_true(self, val: &Value) -> bool {
+        match self {
+            Truthiness::Strict => val.as_bool().unwrap_or(false),
+            Truthiness::Loose => match val {
+                Value::Null => false,
+                Value::Bool(val) => *val,
+                Value::Number(val) => val.as_f64().is_some_and(|val| val != 0.0),
+                Value::String(val) => !val.is_empty(),
+                Value::Array(val) => !val.is_empty(),
+                Value::Object(val) => !val.is_empty(),
+            },
+        }
+    }
+}
+
+/// What a value token displays when its value is missing from the modal, such as a misspelled key or an index
+/// past the end of a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Undefined {
+    /// The value is displayed as empty.
+    #[default]
+    Lenient,
+    /// Rendering fails with a `RenderError::Undefined` naming the missing path, wherever the path is used, unless
+    /// it is guarded by `??`, `?.` or the `default` filter.
+    Strict,
+    /// A placeholder naming the missing path is displayed, such as `[undefined: user.nickname]`.
+    Debug,
+}
+
+/// How the end of a block's body is marked, for templates that do not choose with `@syntax`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BlockSyntax {
+    /// The body is wrapped in `{...}`, and braces in the body must be balanced or escaped.
+    #[default]
+    Braces,
+    /// The body runs up to an end tag such as `@endif;`, and braces in the body are plain text.
+    EndTags,
+}
+
+/// Holds the options, filters and functions used to compile and render templates. A single engine can render any
+/// number of templates, and a template keeps the engine that compiled it for `Template::render`.
+///
+/// # Examples
+///
+/// ```
+/// let engine = Engine::new()
+///     .with_truthiness(Truthiness::Loose)
+///     .with_filter("badge", |val: &Value, _args: &[Value]| {
+///         Ok(Value::from(format!("badge-{}", val.as_str().unwrap_or("none"))))
+///     });
+/// let template = engine.compile("@if:str_vec;{has items} @value:status|badge;")?;
+///
+/// let result = engine.render(&template, &user);
+/// ```
+#[derive(Clone, Default)]
+pub struct Engine {
+    truthiness: Truthiness,
+    block_syntax: BlockSyntax,
+    trim_blocks: bool,
+    undefined: Undefined,
+    filters: HashMap<String, Arc<dyn TemplateFilter>>,
+    functions: HashMap<String, Arc<dyn TemplateFunction>>,
+    tokens: HashMap<String, CustomToken>,
+    template_root: Option<PathBuf>,
+}
+
+#[allow(dead_code)]
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    /// Sets the rules used to decide whether conditions are true.
+    pub fn with_truthiness(mut self, truthiness: Truthiness) -> Engine {
+        self.truthiness = truthiness;
+        self
+    }
+
+    pub fn truthiness(&self) -> Truthiness {
+        self.truthiness
+    }
+
+    /// Sets the block syntax of templates that do not choose one with `@syntax`.
+    pub fn with_block_syntax(mut self, block_syntax: BlockSyntax) -> Engine {
+        self.block_syntax = block_syntax;
+        self
+    }
+
+    pub fn block_syntax(&self) -> BlockSyntax {
+        self.block_syntax
+    }
+
+    /// Drops the line break after each block tag, and the indentation before a block tag that starts its line,
+    /// so lines holding nothing but block tags are left out of the output.
+    pub fn with_trim_blocks(mut self, trim_blocks: bool) -> Engine {
+        self.trim_blocks = trim_blocks;
+        self
+    }
+
+    pub fn trim_blocks(&self) -> bool {
+        self.trim_blocks
+    }
+
+    /// Sets what is displayed for values missing from the modal.
+    pub fn with_undefined(mut self, undefined: Undefined) -> Engine {
+        self.undefined = undefined;
+        self
+    }
+
+    pub fn undefined(&self) -> Undefined {
+        self.undefined
+    }
+
+    /// Registers a filter, used in expressions as `value|name`. A filter with the same name as a built in filter
+    /// replaces it.
+    pub fn with_filter(mut self, name: &str, filter: impl TemplateFilter + 'static) -> Engine {
+        self.filters.insert(name.to_string(), Arc::new(filter));
+        self
+    }
+
+    /// Registers a function, used in expressions as `name(args)`. A function with the same name as a built in
+    /// function replaces it.
+    pub fn with_function(
+        mut self,
+        name: &str,
+        function: impl TemplateFunction + 'static,
+    ) -> Engine {
+        self.functions.insert(name.to_string(), Arc::new(function));
+        self
+    }
+
+    /// Sets the directory that `@include` and `Engine::compile_file` load templates from.
+    pub fn with_template_root(mut self, root: impl Into<PathBuf>) -> Engine {
+        self.template_root = Some(root.into());
+        self
+    }
+
+    /// Reads the source of a template from the template root. Names must be relative and stay within the root.
+    pub fn load_source(&self, name: &str) -> Result<String, String> {
+        let Some(root) = &self.template_root else {
+            return Err(format!("cannot load `{}`, no template root is set", name));
+        };
+        let relative = std::path::Path::new(name);
+
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(format!("`{}` is outside of the template root", name));
+        }
+
+        read_to_string(root.join(relative)).map_err(|e| format!("cannot load `{}`: {}", name, e))
+    }
+
+    /// Loads and parses the named template from the template root.
+    pub fn compile_file(&self, name: &str) -> Result<Template, TemplateError> {
+        let source = self
+            .load_source(name)
+            .map_err(|msg| TemplateError::at(name, "", 0, TemplateErrorKind::IncludeFailed(msg)))?;
+
+        self.compile_named(name, &source)
+    }
+
+    /// Registers an inline token type, used as `@name:key;`. Token types html_modal already has cannot be
+    /// replaced.
+    pub fn with_token(self, name: &str, handler: impl TokenHandler + 'static) -> Engine {
+        self.with_custom_token(name, false, Arc::new(handler))
+    }
+
+    /// Registers a block token type, used as `@name:key;{...}`. The handler decides whether, and how many times,
+    /// the body is displayed.
+    pub fn with_block_token(self, name: &str, handler: impl TokenHandler + 'static) -> Engine {
+        self.with_custom_token(name, true, Arc::new(handler))
+    }
+
+    fn with_custom_token(
+        mut self,
+        name: &str,
+        block: bool,
+        handler: Arc<dyn TokenHandler>,
+    ) -> Engine {
+        self.tokens
+            .insert(name.to_lowercase(), CustomToken { block, handler });
+        self
+    }
+
+    /// The registered token type with the supplied lowercase name.
+    pub fn custom_token(&self, name: &str) -> Option<&CustomToken> {
+        self.tokens.get(name)
+    }
+
+    pub fn has_filter(&self, name: &str) -> bool {
+        self.filters.contains_key(name) || filters::builtin(name).is_some()
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name) || functions::builtin(name).is_some()
+    }
+
+    /// Applies the named filter, preferring a registered filter over a built in one.
+    pub fn apply_filter(
+        &self,
+        name: &str,
+        val: &Value,
+        args: &[Value],
+    ) -> Result<Value, RenderError> {
+        let result = match (self.filters.get(name), filters::builtin(name)) {
+            (Some(filter), _) => filter.apply(val, args),
+            (None, Some(filter)) => Ok(filter(val, args)),
+            (None, None) => Err(String::from("filter is not registered")),
+        };
+
+        result.map_err(|message| RenderError::Filter {
+            name: name.to_string(),
+            message,
+        })
+    }
+
+    /// Calls the named function, preferring a registered function over a built in one.
+    pub fn call_function(&self, name: &str, args: &[Value]) -> Result<Value, RenderError> {
+        let result = match (self.functions.get(name), functions::builtin(name)) {
+            (Some(function), _) => function.call(args),
+            (None, Some(function)) => function(args),
+            (None, None) => Err(String::from("function is not registered")),
+        };
+
+        result.map_err(|message| RenderError::Function {
+            name: name.to_string(),
+            message,
+        })
+    }
+
+    /// Parses the supplied template source, allowing the filters and functions registered on this engine.
+    pub fn compile(&self, source: &str) -> Result<Template, TemplateError> {
+        Template::compile_with(self, source)
+    }
+
+    /// Parses the supplied template source, reporting errors against the supplied template name.
+    pub fn compile_named(&self, name: &str, source: &str) -> Result<Template, TemplateError> {
+        Template::compile_named_with(self, name, source)
+    }
+
+    /// Renders the template against the supplied modal.
+    pub fn render<T: serde::ser::Serialize>(&self, template: &Template, modal: &T) -> String {
+        let json_value: Value = serde_json::to_value(modal).unwrap_or_default();
+        self.render_value(template, &json_value)
+    }
+
+    /// Renders the template against a modal that has already been converted to a serde Value.
+    pub fn render_value(&self, template: &Template, modal: &Value) -> String {
+        let mut renderer = Renderer::new(self, template, modal);
+        renderer.render_nodes(template.nodes());
+        renderer.finish()
+    }
+
+    /// Renders the template against the supplied modal. Unlike `render`, which displays a value that failed to
+    /// evaluate as empty, the first filter or function error is returned.
+    pub fn try_render<T: serde::ser::Serialize>(
+        &self,
+        template: &Template,
+        modal: &T,
+    ) -> Result<String, RenderError> {
+        let json_value: Value = serde_json::to_value(modal).unwrap_or_default();
+        self.try_render_value(template, &json_value)
+    }
+
+    /// Renders the template against a modal that has already been converted to a serde Value, returning the first
+    /// filter or function error.
+    pub fn try_render_value(
+        &self,
+        template: &Template,
+        modal: &Value,
+    ) -> Result<String, RenderError> {
+        let mut renderer = Renderer::new(self, template, modal);
+        renderer.render_nodes(template.nodes());
+        renderer.into_result()
+    }
+}
+
+impl fmt::Debug for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut filters: Vec<&String> = self.filters.keys().collect();
+        let mut functions: Vec<&String> = self.functions.keys().collect();
+        let mut tokens: Vec<&String> = self.tokens.keys().collect();
+        filters.sort();
+        functions.sort();
+        tokens.sort();
+
+        f.debug_struct("Engine")
+            .field("truthiness", &self.truthiness)
+            .field("block_syntax", &self.block_syntax)
+            .field("trim_blocks", &self.trim_blocks)
+            .field("undefined", &self.undefined)
+            .field("filters", &filters)
+            .field("functions", &functions)
+            .field("tokens", &tokens)
+            .field("template_root", &self.template_root)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_truthiness_strict() {
+        assert!(Truthiness::Strict.is_true(&json!(true)));
+        assert!(!Truthiness::Strict.is_true(&json!(1)));
+        assert!(!Truthiness::Strict.is_true(&json!(["a"])));
+    }
+
+    #[test]
+    fn test_truthiness_loose() {
+        for val in [
+            json!(true),
+            json!(-1),
+            json!(0.5),
+            json!("a"),
+            json!([0]),
+            json!({"a": 1}),
+        ] {
+            assert!(Truthiness::Loose.is_true(&val), "{} should be true", val);
+        }
+
+        for val in [
+            json!(false),
+            json!(null),
+            json!(0),
+            json!(0.0),
+            json!(""),
+            json!([]),
+            json!({}),
+        ] {
+            assert!(!Truthiness::Loose.is_true(&val), "{} should be false", val);
+        }
+    }
+
+    #[test]
+    fn test_engine_truthiness() {
+        let template = Template::compile("@if:str_vec;{items}@else{empty}").unwrap();
+        let modal = json!({"str_vec": ["a"]});
+
+        assert_eq!(Engine::new().render_value(&template, &modal), "empty");
+        assert_eq!(
+            Engine::new()
+                .with_truthiness(Truthiness::Loose)
+                .render_value(&template, &modal),
+            "items"
+        );
+        assert_eq!(
+            Engine::new()
+                .with_truthiness(Truthiness::Loose)
+                .render_value(&template, &json!({"str_vec": []})),
+            "empty"
+        );
+    }
+
+    #[test]
+    fn test_engine_undefined() {
+        let template = Template::compile(
+            "@value:str_vec[9];|@value:nickname|default(name);|@forvalue:3.name;",
+        )
+        .unwrap();
+        let modal = json!({"str_vec": ["a"], "name": "Al"});
+
+        assert_eq!(Engine::new().render_value(&template, &modal), "|Al|");
+        assert_eq!(
+            Engine::new()
+                .with_undefined(Undefined::Debug)
+                .render_value(&template, &modal),
+            "[undefined: str_vec[9]]|Al|[undefined: 3.name]"
+        );
+        assert_eq!(
+            Engine::new()
+                .with_undefined(Undefined::Strict)
+                .try_render_value(&template, &modal),
+            Err(RenderError::Undefined {
+                path: String::from("str_vec[9]")
+            })
+        );
+        assert_eq!(
+            Engine::new()
+                .with_undefined(Undefined::Strict)
+                .try_render_value(
+                    &Template::compile("@for:user in users;{@value:user.nmae;}").unwrap(),
+                    &json!({"users": [{"name": "Al"}]})
+                ),
+            Err(RenderError::Undefined {
+                path: String::from("user.nmae")
+            })
+        );
+        assert_eq!(
+            Engine::new()
+                .with_undefined(Undefined::Strict)
+                .try_render_value(
+                    &Template::compile("@value:nickname ?? name;|@value:plan?.name;").unwrap(),
+                    &json!({"name": "Al", "plan": null})
+                ),
+            Ok(String::from("Al|"))
+        );
+
+        for (source, path) in [
+            ("@if:!user.is_admn;{admin}", "user.is_admn"),
+            ("@if:typo == \"a\";{a}", "typo"),
+            ("@value:typo|upper;", "typo"),
+        ] {
+            assert_eq!(
+                Engine::new()
+                    .with_undefined(Undefined::Strict)
+                    .try_render_value(
+                        &Template::compile(source).unwrap(),
+                        &json!({"user": {"is_admin": true}})
+                    ),
+                Err(RenderError::Undefined {
+                    path: String::from(path)
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_engine_loose_operators() {
+        let template = Template::compile("@if:!name && count;{yes}@else{no}").unwrap();
+        let engine = Engine::new().with_truthiness(Truthiness::Loose);
+
+        assert_eq!(
+            engine.render_value(&template, &json!({"name": "", "count": 2})),
+            "yes"
+        );
+        assert_eq!(
+            engine.render_value(&template, &json!({"name": "", "count": 0})),
+            "no"
+        );
+    }
+
+    #[test]
+    fn test_engine_custom_filter() {
+        let engine = Engine::new().with_filter("badge", |val: &Value, args: &[Value]| {
+            let prefix = args.first().and_then(Value::as_str).unwrap_or("badge");
+
+            match val.as_str() {
+                Some(status) => Ok(Value::from(format!("{}-{}", prefix, status))),
+                None => Err(String::from("expected a status string")),
+            }
+        });
+        let template = engine
+            .compile(
+                "<span class=\"@value:status|badge;\">@value:status|badge(\"tag\")|upper;</span>",
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.render_value(&template, &json!({"status": "active"})),
+            "<span class=\"badge-active\">TAG-ACTIVE</span>"
+        );
+    }
+
+    #[test]
+    fn test_engine_custom_filter_error() {
+        let engine = Engine::new().with_filter("badge", |_val: &Value, _args: &[Value]| {
+            Err(String::from("expected a status string"))
+        });
+        let template = engine.compile("[@value:status|badge;]").unwrap();
+        let modal = json!({"status": 3});
+
+        assert_eq!(engine.render_value(&template, &modal), "[]");
+        assert_eq!(
+            engine.try_render_value(&template, &modal),
+            Err(RenderError::Filter {
+                name: String::from("badge"),
+                message: String::from("expected a status string"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_engine_custom_function() {
+        let engine = Engine::new().with_function("plan_name", |args: &[Value]| {
+            match args.first().and_then(Value::as_u64) {
+                Some(1) => Ok(Value::from("Starter")),
+                _ => Ok(Value::from("Unknown")),
+            }
+        });
+        let template = engine
+            .compile("@value:plan_name(plan_id)|upper;@if:plan_name(2) == \"Unknown\";{!}")
+            .unwrap();
+
+        assert_eq!(
+            engine.render_value(&template, &json!({"plan_id": 1})),
+            "STARTER!"
+        );
+    }
+
+    #[test]
+    fn test_template_renders_with_compiling_engine() {
+        let engine = Engine::new()
+            .with_filter("badge", |val: &Value, _args: &[Value]| {
+                Ok(Value::from(format!(
+                    "[{}]",
+                    val.as_str().unwrap_or_default()
+                )))
+            })
+            .with_function("plan_name", |_args: &[Value]| Ok(Value::from("Starter")));
+        let template = engine
+            .compile("@value:status|badge; @value:plan_name();")
+            .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"status": "ok"})),
+            "[ok] Starter"
+        );
+        assert_eq!(template.render(&json!({"status": "ok"})), "[ok] Starter");
+    }
+
+    #[test]
+    fn test_engine_compile_unregistered() {
+        let error = Template::compile("@value:status|badge;").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnknownFilter(String::from("badge"))
+        );
+
+        let error = Engine::new().compile("@value:plan_name(1);").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnknownFunction(String::from("plan_name"))
+        );
+    }
+
+    #[test]
+    fn test_engine_override_builtin_filter() {
+        let engine = Engine::new().with_filter("upper", |_val: &Value, _args: &[Value]| {
+            Ok(Value::from("x"))
+        });
+        let template = engine.compile("@value:name|upper;").unwrap();
+
+        assert_eq!(engine.render_value(&template, &json!({"name": "a"})), "x");
+    }
+}
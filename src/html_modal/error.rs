@@ -0,0 +1,214 @@
+use std::fmt;
+
+const MAX_SNIPPET_LEN: usize = 80;
+
+/// What went wrong while compiling or rendering a template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateErrorKind {
+    /// A block was opened with `{` but its closing `}` was never found.
+    UnterminatedBlock,
+    /// A token was started but its closing `;` was never found on the same line.
+    UnterminatedToken,
+    /// A comment was opened with `@*` but its closing `*@` was never found.
+    UnterminatedComment,
+    /// A block token was not followed by a `{` body.
+    MissingBlock,
+    /// A block using end tags was not closed by the named end tag, such as `endif`.
+    MissingEndTag(String),
+    /// `@syntax` named a block syntax html_modal does not know about.
+    UnknownSyntax(String),
+    /// A token that belongs to a block, such as `@else`, was found without that block.
+    UnexpectedToken(String),
+    /// The token type is not one html_modal knows about.
+    UnknownTokenType(String),
+    /// The token key is longer than the maximum allowed length.
+    KeyTooLong,
+    /// An index or loop level in a key could not be parsed.
+    BadIndex(String),
+    /// An expression, such as the condition of an if, could not be parsed.
+    InvalidExpression(String),
+    /// A filter was applied that the engine does not know about.
+    UnknownFilter(String),
+    /// A function was called that the engine does not know about.
+    UnknownFunction(String),
+    /// An included template could not be loaded.
+    IncludeFailed(String),
+    /// A template includes or extends itself, directly or through other templates. Holds the chain of templates.
+    IncludeCycle(String),
+    /// Two blocks in the same template have the same name.
+    DuplicateBlock(String),
+    /// Two macros have the same name.
+    DuplicateMacro(String),
+    /// A token's output would be escaped for a different part of the HTML document than the one it is written
+    /// into, such as a block overriding a layout's block that sits inside a `<script>`.
+    ContextMismatch(String),
+}
+
+impl fmt::Display for TemplateErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateErrorKind::UnterminatedBlock => write!(f, "block is missing its closing `}}`"),
+            TemplateErrorKind::UnterminatedToken => write!(f, "token is missing its closing `;`"),
+            TemplateErrorKind::UnterminatedComment => {
+                write!(f, "comment is missing its closing `*@`")
+            }
+            TemplateErrorKind::MissingBlock => write!(f, "expected `{{` to open the block"),
+            TemplateErrorKind::MissingEndTag(end_tag) => {
+                write!(f, "block is missing its closing `@{};`", end_tag)
+            }
+            TemplateErrorKind::UnknownSyntax(syntax) => {
+                write!(
+                    f,
+                    "unknown block syntax `{}`, expected `braces` or `endtags`",
+                    syntax
+                )
+            }
+            TemplateErrorKind::UnexpectedToken(token_type) => {
+                write!(
+                    f,
+                    "`@{}` does not follow the block it belongs to",
+                    token_type
+                )
+            }
+            TemplateErrorKind::UnknownTokenType(token_type) => {
+                write!(f, "unknown token type `{}`", token_type)
+            }
+            TemplateErrorKind::KeyTooLong => write!(f, "token key is too long"),
+            TemplateErrorKind::BadIndex(msg) => write!(f, "{}", msg),
+            TemplateErrorKind::InvalidExpression(msg) => write!(f, "{}", msg),
+            TemplateErrorKind::UnknownFilter(name) => write!(f, "unknown filter `{}`", name),
+            TemplateErrorKind::UnknownFunction(name) => write!(f, "unknown function `{}`", name),
+            TemplateErrorKind::IncludeFailed(msg) => write!(f, "{}", msg),
+            TemplateErrorKind::IncludeCycle(chain) => write!(f, "include cycle: {}", chain),
+            TemplateErrorKind::DuplicateBlock(name) => {
+                write!(f, "block `{}` is defined more than once", name)
+            }
+            TemplateErrorKind::DuplicateMacro(name) => {
+                write!(f, "macro `{}` is defined more than once", name)
+            }
+            TemplateErrorKind::ContextMismatch(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// An error found in a template, pointing at the line and column where it occurred.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateError {
+    /// The name the template was compiled with.
+    pub name: String,
+    /// 1 based line of the error.
+    pub line: usize,
+    /// 1 based column of the error, counted in characters.
+    pub column: usize,
+    /// The source line the error occurred on.
+    pub snippet: String,
+    pub kind: TemplateErrorKind,
+}
+
+impl TemplateError {
+    /// Builds an error for the byte offset `pos` within `source`.
+    pub fn at(name: &str, source: &str, pos: usize, kind: TemplateErrorKind) -> TemplateError {
+        let pos = pos.min(source.len());
+        let before = &source[..pos];
+        let line_start = before.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let line_end = source[pos..]
+            .find('\n')
+            .map(|idx| pos + idx)
+            .unwrap_or(source.len());
+
+        TemplateError {
+            name: name.to_string(),
+            line: before.matches('\n').count() + 1,
+            column: source[line_start..pos].chars().count() + 1,
+            snippet: source[line_start..line_end]
+                .trim_end()
+                .chars()
+                .take(MAX_SNIPPET_LEN)
+                .collect(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            self.name, self.line, self.column, self.kind
+        )?;
+        writeln!(f, "    {}", self.snippet)?;
+        write!(f, "    {:>width$}", "^", width = self.column)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// An error found while rendering a compiled template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderError {
+    /// A filter returned an error, or is not known to the engine rendering the template.
+    Filter { name: String, message: String },
+    /// A function returned an error, or is not known to the engine rendering the template.
+    Function { name: String, message: String },
+    /// A custom token's handler returned an error, or is not known to the engine rendering the template.
+    Token { name: String, message: String },
+    /// A macro call could not be displayed, because the macro is not defined or calls nest too deeply.
+    Macro { name: String, message: String },
+    /// Arithmetic could not be done, such as a division by zero or adding a number to a collection.
+    Arithmetic { message: String },
+    /// A path is missing from the modal, while rendering with `Undefined::Strict`.
+    Undefined { path: String },
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Filter { name, message } => write!(f, "filter `{}`: {}", name, message),
+            RenderError::Function { name, message } => {
+                write!(f, "function `{}`: {}", name, message)
+            }
+            RenderError::Token { name, message } => write!(f, "token `@{}`: {}", name, message),
+            RenderError::Macro { name, message } => write!(f, "macro `{}`: {}", name, message),
+            RenderError::Arithmetic { message } => write!(f, "arithmetic: {}", message),
+            RenderError::Undefined { path } => write!(f, "`{}` is not defined", path),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_position() {
+        let source = "line one\n  @if:x;{\nline three";
+        let error = TemplateError::at(
+            "page.html",
+            source,
+            11,
+            TemplateErrorKind::UnterminatedBlock,
+        );
+
+        assert_eq!(error.line, 2);
+        assert_eq!(error.column, 3);
+        assert_eq!(error.snippet, "  @if:x;{");
+    }
+
+    #[test]
+    fn test_error_display() {
+        let error = TemplateError::at(
+            "page.html",
+            "ab@oops:x;",
+            2,
+            TemplateErrorKind::UnknownTokenType(String::from("oops")),
+        );
+
+        assert_eq!(
+            error.to_string(),
+            "page.html:1:3: unknown token type `oops`\n    ab@oops:x;\n      ^"
+        );
+    }
+}
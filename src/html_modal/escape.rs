@@ -0,0 +1,360 @@
+use super::path::display_string;
+use serde_json::Value;
+
+/// Written in place of a URL or CSS value that could run script, such as a `javascript:` URL.
+pub const BLOCKED_VALUE: &str = "blocked";
+
+/// How a displayed value is escaped before it is written to the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escape {
+    /// Escapes the characters that are special in HTML text.
+    Html,
+    /// Writes the value untouched. Only for trusted markup.
+    Raw,
+    /// A value inside a tag. `quoted` is false for unquoted attribute values, or values between attributes.
+    Attr { quoted: bool, value: AttrValue },
+    /// A value inside a `<script>` element.
+    Script(JsState),
+    /// A value inside a `<style>` element.
+    Style(CssState),
+}
+
+/// The kind of attribute a value is being written into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttrValue {
+    Text,
+    /// A `srcdoc` document, escaped as HTML before being escaped as an attribute.
+    Html,
+    Url(UrlPart),
+    Js(JsState),
+    Css(CssState),
+}
+
+/// Where in a URL the value is being written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UrlPart {
+    /// The start of the URL, where the value decides the scheme.
+    Start,
+    /// After the start of the URL, before any `?` or `#`.
+    Path,
+    /// After a `?` or `#`.
+    Query,
+}
+
+/// Where in JavaScript a value is being written. Values are written as quoted literals everywhere except inside
+/// string literals, so a comment or regular expression the context could not follow still cannot run a value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsState {
+    Code,
+    /// Inside a string or template literal with the given quote.
+    String(u8),
+    /// Inside a `//` comment.
+    LineComment,
+    /// Inside a `/* */` comment.
+    BlockComment,
+    /// Inside a regular expression literal.
+    Regex,
+}
+
+/// Whether a CSS value is written as a property value, inside a string with the given quote, or inside a
+/// `/* */` comment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CssState {
+    Code,
+    String(u8),
+    Comment,
+}
+
+impl Escape {
+    pub fn write(self, val: &Value, out: &mut String) {
+        match self {
+            Escape::Html => escape_html(&display_string(val), out),
+            Escape::Raw => out.push_str(&display_string(val)),
+            Escape::Attr { quoted, value } => {
+                let mut inner = String::new();
+
+                match value {
+                    AttrValue::Text => inner.push_str(&display_string(val)),
+                    AttrValue::Html => escape_html(&display_string(val), &mut inner),
+                    AttrValue::Url(part) => escape_url(&display_string(val), part, &mut inner),
+                    AttrValue::Js(state) => escape_js(val, state, &mut inner),
+                    AttrValue::Css(state) => escape_css(&display_string(val), state, &mut inner),
+                }
+
+                if quoted {
+                    escape_html(&inner, out);
+                } else {
+                    escape_unquoted_attr(&inner, out);
+                }
+            }
+            Escape::Script(state) => escape_js(val, state, out),
+            Escape::Style(state) => escape_css(&display_string(val), state, out),
+        }
+    }
+}
+
+/// Escapes `&`, `<`, `>`, `"` and `'` so the value can be placed in HTML text or a quoted attribute.
+pub fn escape_html(val: &str, out: &mut String) {
+    for ch in val.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+}
+
+/// Escapes every character that could end an unquoted attribute value.
+fn escape_unquoted_attr(val: &str, out: &mut String) {
+    for ch in val.chars() {
+        if ch.is_alphanumeric() || matches!(ch, '-' | '_' | '.' | ',' | ':' | '/' | '#' | '%') {
+            out.push(ch);
+        } else {
+            out.push_str(&format!("&#{};", ch as u32));
+        }
+    }
+}
+
+/// Percent encodes a value written into a URL. At the start of a URL only http, https and mailto schemes are
+/// allowed, anything else such as `javascript:` is replaced with `#blocked`.
+fn escape_url(val: &str, part: UrlPart, out: &mut String) {
+    match part {
+        UrlPart::Start => {
+            if let Some(scheme_end) = val.find(':')
+                && !val[..scheme_end].contains(['/', '?', '#'])
+            {
+                let scheme = val[..scheme_end].trim().to_lowercase();
+
+                if !matches!(scheme.as_str(), "http" | "https" | "mailto") {
+                    out.push('#');
+                    out.push_str(BLOCKED_VALUE);
+                    return;
+                }
+            }
+
+            normalize_url(val, out);
+        }
+        UrlPart::Path => normalize_url(val, out),
+        UrlPart::Query => {
+            for byte in val.bytes() {
+                if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+                    out.push(byte as char);
+                } else {
+                    out.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+    }
+}
+
+/// Percent encodes characters that are never valid in a URL, keeping the URL's structure intact.
+fn normalize_url(val: &str, out: &mut String) {
+    for byte in val.bytes() {
+        if byte.is_ascii_alphanumeric()
+            || matches!(
+                byte,
+                b'-' | b'.'
+                    | b'_'
+                    | b'~'
+                    | b':'
+                    | b'/'
+                    | b'?'
+                    | b'#'
+                    | b'['
+                    | b']'
+                    | b'@'
+                    | b'!'
+                    | b'$'
+                    | b'&'
+                    | b'*'
+                    | b'+'
+                    | b','
+                    | b';'
+                    | b'='
+                    | b'%'
+            )
+        {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+}
+
+/// Writes the value as a JavaScript literal, or as the contents of a string literal. Characters that could end
+/// the script element or the string, or start a `${...}` substitution in a template literal, are written as
+/// unicode escapes. Inside comments and regular expressions, `/` is escaped too so the literal cannot end them.
+fn escape_js(val: &Value, state: JsState, out: &mut String) {
+    let in_string = matches!(state, JsState::String(_));
+    let literal = match state {
+        JsState::String(_) => display_string(val),
+        _ => serde_json::to_string(val).unwrap_or_default(),
+    };
+
+    for ch in literal.chars() {
+        match ch {
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                out.push_str(&format!("\\u{:04x}", ch as u32))
+            }
+            '/' if !in_string && state != JsState::Code => out.push_str("\\/"),
+            '\'' | '`' | '$' | '{' if in_string => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            '"' | '\\' if in_string => {
+                out.push('\\');
+                out.push(ch);
+            }
+            '\n' if in_string => out.push_str("\\n"),
+            '\r' if in_string => out.push_str("\\r"),
+            _ => out.push(ch),
+        }
+    }
+}
+
+/// Writes a CSS property value, or the contents of a CSS string. Property values and comments may only hold
+/// simple keywords, numbers and colors, anything else is replaced with `blocked`.
+fn escape_css(val: &str, state: CssState, out: &mut String) {
+    match state {
+        CssState::Code | CssState::Comment => {
+            if val.chars().all(|ch| {
+                ch.is_ascii_alphanumeric() || matches!(ch, ' ' | '#' | '.' | ',' | '%' | '-' | '_')
+            }) {
+                out.push_str(val);
+            } else {
+                out.push_str(BLOCKED_VALUE);
+            }
+        }
+        CssState::String(_) => {
+            for ch in val.chars() {
+                if ch.is_alphanumeric() || ch == ' ' {
+                    out.push(ch);
+                } else {
+                    out.push_str(&format!("\\{:x} ", ch as u32));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn write(escape: Escape, val: Value) -> String {
+        let mut out = String::new();
+        escape.write(&val, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_escape_html() {
+        let mut out = String::new();
+        escape_html(r#"<a href="x">Tom & 'Jerry'</a>"#, &mut out);
+
+        assert_eq!(
+            out,
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_escape_raw() {
+        assert_eq!(write(Escape::Raw, json!("<b>bold</b>")), "<b>bold</b>");
+    }
+
+    #[test]
+    fn test_escape_unquoted_attr() {
+        let escape = Escape::Attr {
+            quoted: false,
+            value: AttrValue::Text,
+        };
+
+        assert_eq!(write(escape, json!("a b>")), "a&#32;b&#62;");
+    }
+
+    #[test]
+    fn test_escape_url_blocks_javascript() {
+        let escape = Escape::Attr {
+            quoted: true,
+            value: AttrValue::Url(UrlPart::Start),
+        };
+
+        assert_eq!(write(escape, json!(" JavaScript:alert(1)")), "#blocked");
+        assert_eq!(
+            write(escape, json!("https://example.com/a b")),
+            "https://example.com/a%20b"
+        );
+        assert_eq!(write(escape, json!("/login?next=/")), "/login?next=/");
+    }
+
+    #[test]
+    fn test_escape_srcdoc() {
+        let escape = Escape::Attr {
+            quoted: true,
+            value: AttrValue::Html,
+        };
+
+        assert_eq!(
+            write(escape, json!("<script>alert(1)</script>")),
+            "&amp;lt;script&amp;gt;alert(1)&amp;lt;/script&amp;gt;"
+        );
+    }
+
+    #[test]
+    fn test_escape_url_query() {
+        let escape = Escape::Attr {
+            quoted: true,
+            value: AttrValue::Url(UrlPart::Query),
+        };
+
+        assert_eq!(write(escape, json!("a&b=c d")), "a%26b%3Dc%20d");
+    }
+
+    #[test]
+    fn test_escape_script() {
+        assert_eq!(
+            write(Escape::Script(JsState::Code), json!("</script>")),
+            "\"\\u003c/script\\u003e\""
+        );
+        assert_eq!(write(Escape::Script(JsState::Code), json!(3)), "3");
+        assert_eq!(
+            write(Escape::Script(JsState::String(b'\'')), json!("it's \"x\"")),
+            "it\\u0027s \\\"x\\\""
+        );
+        assert_eq!(
+            write(Escape::Script(JsState::String(b'`')), json!("${alert(1)}")),
+            "\\u0024\\u007balert(1)}"
+        );
+    }
+
+    #[test]
+    fn test_escape_js_attr() {
+        let escape = Escape::Attr {
+            quoted: true,
+            value: AttrValue::Js(JsState::Code),
+        };
+
+        assert_eq!(write(escape, json!("a")), "&quot;a&quot;");
+    }
+
+    #[test]
+    fn test_escape_style() {
+        assert_eq!(
+            write(Escape::Style(CssState::Code), json!("#ff0000")),
+            "#ff0000"
+        );
+        assert_eq!(
+            write(
+                Escape::Style(CssState::Code),
+                json!("red;background:url(x)")
+            ),
+            "blocked"
+        );
+        assert_eq!(
+            write(Escape::Style(CssState::String(b'"')), json!("a\"b")),
+            "a\\22 b"
+        );
+    }
+}
@@ -0,0 +1,830 @@
+use super::engine::Engine;
+use super::error::{RenderError, TemplateErrorKind};
+use super::path::{Path, display_string, resolve_sliced};
+use serde_json::{Number, Value};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// A comparison between two values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// An arithmetic operator. `+` also joins strings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// A parsed expression such as `!user.is_admin`, `count > 0`, `a && (b || c)` or `name|upper`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Path(Path),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    /// The value of the left expression, or of the right expression when the left is null or missing, such as
+    /// `nickname ?? name`.
+    Coalesce(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+    /// Arithmetic such as `price * qty` or `page + 1`. Integers stay integers unless a float is involved, and a
+    /// null on either side makes the result null.
+    Arith(ArithOp, Box<Expr>, Box<Expr>),
+    /// Passes the value of the expression through the named filter, such as `name|truncate(20)`.
+    Filter {
+        expr: Box<Expr>,
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// Calls the named function, such as `plan_name(plan_id)`.
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(Value),
+    Path(String),
+    Not,
+    And,
+    Or,
+    Coalesce,
+    Compare(CompareOp),
+    Arith(ArithOp),
+    Open,
+    Close,
+    Pipe,
+    Comma,
+}
+
+impl Expr {
+    /// Parses an expression, allowing the filters and functions registered on the engine.
+    pub fn parse_with(src: &str, engine: &Engine) -> Result<Expr, TemplateErrorKind> {
+        let mut parser = ExprParser {
+            engine,
+            tokens: tokenize(src).map_err(TemplateErrorKind::InvalidExpression)?,
+            pos: 0,
+        };
+
+        let expr = parser.parse_coalesce()?;
+
+        if parser.pos < parser.tokens.len() {
+            return Err(invalid(format!(
+                "Unexpected `{}` in expression!",
+                src.trim()
+            )));
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses a comma separated list of expressions, such as the arguments of a macro call.
+    pub fn parse_list(src: &str, engine: &Engine) -> Result<Vec<Expr>, TemplateErrorKind> {
+        let mut parser = ExprParser {
+            engine,
+            tokens: tokenize(src).map_err(TemplateErrorKind::InvalidExpression)?,
+            pos: 0,
+        };
+        parser.tokens.push(Token::Close);
+
+        let list = parser.parse_args()?;
+
+        if parser.pos < parser.tokens.len() {
+            return Err(invalid(format!("Unexpected `{}` in list!", src.trim())));
+        }
+
+        Ok(list)
+    }
+
+    /// Evaluates the expression, resolving paths, truthiness, filters and functions with the supplied scope.
+    pub fn eval(&self, scope: &impl Scope) -> Result<Value, RenderError> {
+        self.eval_guarded(scope, false)
+    }
+
+    /// Evaluates the expression. Paths that do not resolve are reported to the scope unless `guarded`, which is
+    /// set for the left of `??` and the value passed to `default`, where a missing value is expected.
+    fn eval_guarded(&self, scope: &impl Scope, guarded: bool) -> Result<Value, RenderError> {
+        let eval = |expr: &Expr| expr.eval_guarded(scope, guarded);
+        let eval_bool = |expr: &Expr| Ok::<_, RenderError>(scope.is_true(&eval(expr)?));
+
+        Ok(match self {
+            Expr::Literal(val) => val.clone(),
+            Expr::Path(path) => match lookup_sliced(path, scope) {
+                Some(val) => val,
+                None => {
+                    if !guarded {
+                        scope.undefined(path);
+                    }
+
+                    Value::Null
+                }
+            },
+            Expr::Not(expr) => Value::Bool(!eval_bool(expr)?),
+            Expr::And(left, right) => Value::Bool(eval_bool(left)? && eval_bool(right)?),
+            Expr::Or(left, right) => Value::Bool(eval_bool(left)? || eval_bool(right)?),
+            Expr::Coalesce(left, right) => match left.eval_guarded(scope, true)? {
+                Value::Null => eval(right)?,
+                val => val,
+            },
+            Expr::Compare(op, left, right) => {
+                Value::Bool(compare(*op, &eval(left)?, &eval(right)?))
+            }
+            Expr::Arith(op, left, right) => arith(*op, &eval(left)?, &eval(right)?)?,
+            Expr::Filter { expr, name, args } => {
+                let val = expr.eval_guarded(scope, guarded || name == "default")?;
+
+                scope.filter(name, &val, &eval_args(args, scope, guarded)?)?
+            }
+            Expr::Call { name, args } => scope.call(name, &eval_args(args, scope, guarded)?)?,
+        })
+    }
+}
+
+/// Resolves the paths, truthiness, filters and functions used while evaluating an expression.
+pub trait Scope {
+    fn lookup(&self, path: &Path) -> Option<Cow<'_, Value>>;
+
+    /// Called for a path that does not resolve, which evaluates to null.
+    fn undefined(&self, path: &Path);
+
+    fn is_true(&self, val: &Value) -> bool;
+
+    fn filter(&self, name: &str, val: &Value, args: &[Value]) -> Result<Value, RenderError>;
+
+    fn call(&self, name: &str, args: &[Value]) -> Result<Value, RenderError>;
+}
+
+/// Looks up a path through the scope. The part of a path from its first slice on is resolved here, since the
+/// scope can only return references.
+fn lookup_sliced(path: &Path, scope: &impl Scope) -> Option<Value> {
+    let Some(slice_at) = path.slice_start() else {
+        return scope.lookup(path).map(Cow::into_owned);
+    };
+    let head = Path {
+        root: path.root.clone(),
+        segments: path.segments[..slice_at].to_vec(),
+    };
+
+    resolve_sliced(scope.lookup(&head)?.as_ref(), &path.segments[slice_at..])
+}
+
+fn eval_args(args: &[Expr], scope: &impl Scope, guarded: bool) -> Result<Vec<Value>, RenderError> {
+    args.iter()
+        .map(|arg| arg.eval_guarded(scope, guarded))
+        .collect()
+}
+
+fn arith(op: ArithOp, left: &Value, right: &Value) -> Result<Value, RenderError> {
+    let concat = |val: &Value| matches!(val, Value::String(_) | Value::Number(_) | Value::Bool(_));
+
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Number(left), Value::Number(right)) => arith_numbers(op, left, right),
+        (Value::String(_), _) | (_, Value::String(_))
+            if op == ArithOp::Add && concat(left) && concat(right) =>
+        {
+            Ok(Value::String(display_string(left) + &display_string(right)))
+        }
+        _ => Err(arith_error(format!(
+            "cannot use `{}` with {} and {}",
+            op.symbol(),
+            left,
+            right
+        ))),
+    }
+}
+
+/// Integers are kept as integers, so `7 / 2` is `3`. If either side is a float, so is the result.
+fn arith_numbers(op: ArithOp, left: &Number, right: &Number) -> Result<Value, RenderError> {
+    let divides = matches!(op, ArithOp::Div | ArithOp::Rem);
+
+    if let (Some(left), Some(right)) = (left.as_i64(), right.as_i64()) {
+        let result = match op {
+            ArithOp::Add => left.checked_add(right),
+            ArithOp::Sub => left.checked_sub(right),
+            ArithOp::Mul => left.checked_mul(right),
+            ArithOp::Div => left.checked_div(right),
+            ArithOp::Rem => left.checked_rem(right),
+        };
+
+        return match result {
+            Some(result) => Ok(Value::from(result)),
+            None if divides && right == 0 => Err(arith_error(String::from("division by zero"))),
+            None => Err(arith_error(format!(
+                "`{} {} {}` overflows",
+                left,
+                op.symbol(),
+                right
+            ))),
+        };
+    }
+
+    let left = left.as_f64().unwrap_or_default();
+    let right = right.as_f64().unwrap_or_default();
+
+    if divides && right == 0.0 {
+        return Err(arith_error(String::from("division by zero")));
+    }
+
+    let result = match op {
+        ArithOp::Add => left + right,
+        ArithOp::Sub => left - right,
+        ArithOp::Mul => left * right,
+        ArithOp::Div => left / right,
+        ArithOp::Rem => left % right,
+    };
+
+    Number::from_f64(result).map(Value::Number).ok_or_else(|| {
+        arith_error(format!(
+            "`{} {} {}` is not a number",
+            left,
+            op.symbol(),
+            right
+        ))
+    })
+}
+
+fn arith_error(message: String) -> RenderError {
+    RenderError::Arithmetic { message }
+}
+
+impl ArithOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            ArithOp::Add => "+",
+            ArithOp::Sub => "-",
+            ArithOp::Mul => "*",
+            ArithOp::Div => "/",
+            ArithOp::Rem => "%",
+        }
+    }
+}
+
+fn compare(op: CompareOp, left: &Value, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left
+            .as_f64()
+            .zip(right.as_f64())
+            .and_then(|(left, right)| left.partial_cmp(&right)),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        (Value::Bool(left), Value::Bool(right)) => Some(left.cmp(right)),
+        _ => None,
+    };
+
+    match op {
+        CompareOp::Eq => ordering.map_or(left == right, Ordering::is_eq),
+        CompareOp::Ne => ordering.map_or(left != right, Ordering::is_ne),
+        CompareOp::Lt => ordering.is_some_and(Ordering::is_lt),
+        CompareOp::Le => ordering.is_some_and(Ordering::is_le),
+        CompareOp::Gt => ordering.is_some_and(Ordering::is_gt),
+        CompareOp::Ge => ordering.is_some_and(Ordering::is_ge),
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let bytes = src.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let ch = bytes[i];
+        let next = bytes.get(i + 1).copied();
+
+        let token = match (ch, next) {
+            (b' ' | b'\t' | b'\r' | b'\n', _) => {
+                i += 1;
+                continue;
+            }
+            (b'(', _) => Token::Open,
+            (b')', _) => Token::Close,
+            (b'&', Some(b'&')) => Token::And,
+            (b'|', Some(b'|')) => Token::Or,
+            (b'|', _) => Token::Pipe,
+            (b'?', Some(b'?')) => Token::Coalesce,
+            (b',', _) => Token::Comma,
+            (b'=', Some(b'=')) => Token::Compare(CompareOp::Eq),
+            (b'!', Some(b'=')) => Token::Compare(CompareOp::Ne),
+            (b'<', Some(b'=')) => Token::Compare(CompareOp::Le),
+            (b'>', Some(b'=')) => Token::Compare(CompareOp::Ge),
+            (b'!', _) => Token::Not,
+            (b'<', _) => Token::Compare(CompareOp::Lt),
+            (b'>', _) => Token::Compare(CompareOp::Gt),
+            (b'"' | b'\'', _) => {
+                let (val, end) = read_string(src, i)?;
+                tokens.push(Token::Literal(Value::String(val)));
+                i = end;
+                continue;
+            }
+            // a `-` directly before a number is its sign, unless it follows a value and is subtraction.
+            (b'0'..=b'9', _) | (b'-', Some(b'0'..=b'9'))
+                if !(ch == b'-' && ends_value(&tokens)) =>
+            {
+                let start = i;
+                i += 1;
+
+                while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                    i += 1;
+                }
+
+                tokens.push(Token::Literal(parse_number(&src[start..i])?));
+                continue;
+            }
+            (b'+', _) => Token::Arith(ArithOp::Add),
+            (b'-', _) => Token::Arith(ArithOp::Sub),
+            (b'*', _) => Token::Arith(ArithOp::Mul),
+            (b'/', _) => Token::Arith(ArithOp::Div),
+            (b'%', _) => Token::Arith(ArithOp::Rem),
+            _ if ch == b'[' || src[i..].chars().next().is_some_and(is_key_start) => {
+                let start = i;
+
+                let mut in_index = false;
+
+                // a `-` is part of a path inside an index, such as `users[-1]`, or between the letters of a key such
+                // as `user-name`, so subtraction needs whitespace before the `-`.
+                while let Some(c) = src[i..].chars().next() {
+                    let next = src[i + c.len_utf8()..].chars().next();
+                    let in_path = match c {
+                        '.' | '[' | ']' => true,
+                        '?' => next == Some('.'),
+                        '-' => in_index || next.is_some_and(is_key_char),
+                        _ => is_key_char(c),
+                    };
+
+                    if !in_path {
+                        break;
+                    }
+
+                    match c {
+                        '[' => in_index = true,
+                        ']' => in_index = false,
+                        _ => {}
+                    }
+
+                    i += c.len_utf8();
+                }
+
+                tokens.push(match &src[start..i] {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    path => Token::Path(path.to_string()),
+                });
+                continue;
+            }
+            _ => {
+                return Err(format!("Unexpected `{}` in expression!", &src[i..]));
+            }
+        };
+
+        i += match token {
+            Token::And | Token::Or | Token::Coalesce => 2,
+            Token::Compare(CompareOp::Eq | CompareOp::Ne | CompareOp::Le | CompareOp::Ge) => 2,
+            _ => 1,
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Whether a key can start with the character. Keys may use letters of any language.
+fn is_key_start(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_'
+}
+
+fn is_key_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Whether the last token ends a value, so that a following `-` is subtraction rather than a sign.
+fn ends_value(tokens: &[Token]) -> bool {
+    matches!(
+        tokens.last(),
+        Some(Token::Literal(_) | Token::Path(_) | Token::Close)
+    )
+}
+
+/// Reads the quoted string starting at `start`, returning its value and the position after the closing quote.
+fn read_string(src: &str, start: usize) -> Result<(String, usize), String> {
+    let quote = src.as_bytes()[start] as char;
+    let mut val = String::new();
+    let mut chars = src[start + 1..].char_indices();
+
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    val.push(escaped);
+                }
+            }
+            _ if ch == quote => return Ok((val, start + 1 + idx + 1)),
+            _ => val.push(ch),
+        }
+    }
+
+    Err(format!("Unterminated string in `{}`!", src.trim()))
+}
+
+fn parse_number(src: &str) -> Result<Value, String> {
+    if let Ok(int) = src.parse::<i64>() {
+        return Ok(Value::Number(int.into()));
+    }
+
+    src.parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .map(Value::Number)
+        .ok_or_else(|| format!("Invalid number `{}`!", src))
+}
+
+struct ExprParser<'a> {
+    engine: &'a Engine,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    fn next_if(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_coalesce(&mut self) -> Result<Expr, TemplateErrorKind> {
+        let mut expr = self.parse_or()?;
+
+        while self.next_if(&Token::Coalesce) {
+            expr = Expr::Coalesce(Box::new(expr), Box::new(self.parse_or()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, TemplateErrorKind> {
+        let mut expr = self.parse_and()?;
+
+        while self.next_if(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, TemplateErrorKind> {
+        let mut expr = self.parse_compare()?;
+
+        while self.next_if(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_compare()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_compare(&mut self) -> Result<Expr, TemplateErrorKind> {
+        let left = self.parse_sum()?;
+
+        if let Some(Token::Compare(op)) = self.tokens.get(self.pos) {
+            let op = *op;
+            self.pos += 1;
+
+            return Ok(Expr::Compare(
+                op,
+                Box::new(left),
+                Box::new(self.parse_sum()?),
+            ));
+        }
+
+        Ok(left)
+    }
+
+    /// Parses `+` and `-`, which bind less tightly than `*`, `/` and `%`.
+    fn parse_sum(&mut self) -> Result<Expr, TemplateErrorKind> {
+        let mut expr = self.parse_product()?;
+
+        while let Some(Token::Arith(op @ (ArithOp::Add | ArithOp::Sub))) = self.tokens.get(self.pos)
+        {
+            let op = *op;
+            self.pos += 1;
+            expr = Expr::Arith(op, Box::new(expr), Box::new(self.parse_product()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, TemplateErrorKind> {
+        let mut expr = self.parse_not()?;
+
+        while let Some(Token::Arith(op @ (ArithOp::Mul | ArithOp::Div | ArithOp::Rem))) =
+            self.tokens.get(self.pos)
+        {
+            let op = *op;
+            self.pos += 1;
+            expr = Expr::Arith(op, Box::new(expr), Box::new(self.parse_not()?));
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses `!`, which binds more tightly than comparisons and arithmetic, so `!a == b` compares `!a` with `b`.
+    /// Filters still apply first, so `!name|trim` negates the trimmed name.
+    fn parse_not(&mut self) -> Result<Expr, TemplateErrorKind> {
+        if self.next_if(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+
+        self.parse_filter()
+    }
+
+    /// Parses a value followed by any number of `|name` or `|name(args)` filters, applied left to right.
+    fn parse_filter(&mut self) -> Result<Expr, TemplateErrorKind> {
+        let mut expr = self.parse_primary()?;
+
+        while self.next_if(&Token::Pipe) {
+            let name = match self.tokens.get(self.pos) {
+                Some(Token::Path(name)) if !name.contains(['.', '[', ']']) => name.clone(),
+                _ => return Err(invalid(String::from("Expected a filter name after `|`!"))),
+            };
+            self.pos += 1;
+
+            if !self.engine.has_filter(&name) {
+                return Err(TemplateErrorKind::UnknownFilter(name));
+            }
+
+            let args = if self.next_if(&Token::Open) {
+                self.parse_args()?
+            } else {
+                vec![]
+            };
+
+            expr = Expr::Filter {
+                expr: Box::new(expr),
+                name,
+                args,
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, TemplateErrorKind> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+
+        match token {
+            Some(Token::Literal(val)) => Ok(Expr::Literal(val)),
+            Some(Token::Path(name)) if self.next_if(&Token::Open) => {
+                if name.contains(['.', '[', ']']) {
+                    return Err(invalid(format!("Invalid function name `{}`!", name)));
+                }
+
+                if !self.engine.has_function(&name) {
+                    return Err(TemplateErrorKind::UnknownFunction(name));
+                }
+
+                Ok(Expr::Call {
+                    args: self.parse_args()?,
+                    name,
+                })
+            }
+            Some(Token::Path(path)) => Ok(Expr::Path(
+                Path::parse(&path).map_err(TemplateErrorKind::BadIndex)?,
+            )),
+            Some(Token::Open) => {
+                let expr = self.parse_coalesce()?;
+
+                if !self.next_if(&Token::Close) {
+                    return Err(invalid(String::from("Expected `)` in expression!")));
+                }
+
+                Ok(expr)
+            }
+            Some(_) => Err(invalid(String::from("Expected a value in expression!"))),
+            None => Err(invalid(String::from("Expression ended early!"))),
+        }
+    }
+
+    /// Parses the arguments of a filter or function, after the opening `(`.
+    fn parse_args(&mut self) -> Result<Vec<Expr>, TemplateErrorKind> {
+        let mut args = vec![];
+
+        if self.next_if(&Token::Close) {
+            return Ok(args);
+        }
+
+        loop {
+            args.push(self.parse_coalesce()?);
+
+            if self.next_if(&Token::Close) {
+                return Ok(args);
+            }
+
+            if !self.next_if(&Token::Comma) {
+                return Err(invalid(String::from("Expected `,` or `)` in arguments!")));
+            }
+        }
+    }
+}
+
+fn invalid(msg: String) -> TemplateErrorKind {
+    TemplateErrorKind::InvalidExpression(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Parses an expression with the default engine's filters and functions.
+    fn parse(src: &str) -> Result<Expr, TemplateErrorKind> {
+        Expr::parse_with(src, &Engine::default())
+    }
+
+    struct ModalScope<'a>(&'a Value);
+
+    impl Scope for ModalScope<'_> {
+        fn lookup(&self, path: &Path) -> Option<Cow<'_, Value>> {
+            path.resolve(self.0).map(Cow::Borrowed)
+        }
+
+        fn undefined(&self, _path: &Path) {}
+
+        fn is_true(&self, val: &Value) -> bool {
+            val.as_bool().unwrap_or(false)
+        }
+
+        fn filter(&self, name: &str, val: &Value, args: &[Value]) -> Result<Value, RenderError> {
+            Engine::default().apply_filter(name, val, args)
+        }
+
+        fn call(&self, name: &str, args: &[Value]) -> Result<Value, RenderError> {
+            Engine::default().call_function(name, args)
+        }
+    }
+
+    fn eval(src: &str, modal: &Value) -> Value {
+        parse(src).unwrap().eval(&ModalScope(modal)).unwrap()
+    }
+
+    #[test]
+    fn test_expr_path() {
+        let modal = json!({"user": {"is_admin": true, "user-name": "al"}});
+
+        assert_eq!(eval("user.is_admin", &modal), json!(true));
+        assert_eq!(eval("user.user-name", &modal), json!("al"));
+        assert_eq!(eval("prénom|upper", &json!({"prénom": "éa"})), json!("ÉA"));
+        assert_eq!(eval("!user.is_admin", &modal), json!(false));
+        assert_eq!(eval("!!user.is_admin", &modal), json!(true));
+    }
+
+    #[test]
+    fn test_expr_compare() {
+        let modal = json!({"count": 3, "role": "owner", "price": 2.5});
+
+        assert_eq!(eval("count > 0", &modal), json!(true));
+        assert_eq!(eval("count <= 2", &modal), json!(false));
+        assert_eq!(eval("count == 3.0", &modal), json!(true));
+        assert_eq!(eval("price != 2.5", &modal), json!(false));
+        assert_eq!(eval("role == \"owner\"", &modal), json!(true));
+        assert_eq!(eval("role == 'admin'", &modal), json!(false));
+        assert_eq!(eval("missing == null", &modal), json!(true));
+        assert_eq!(eval("role > 3", &modal), json!(false));
+    }
+
+    #[test]
+    fn test_expr_bool_ops() {
+        let modal = json!({"a": true, "b": false, "c": true});
+
+        assert_eq!(eval("a && (b || c)", &modal), json!(true));
+        assert_eq!(eval("a && b || c", &modal), json!(true));
+        assert_eq!(eval("a && !(b || c)", &modal), json!(false));
+        assert_eq!(eval("b || !a", &modal), json!(false));
+    }
+
+    #[test]
+    fn test_expr_not_precedence() {
+        let modal = json!({"name": "Al"});
+
+        assert_eq!(eval("!name == false", &modal), json!(false));
+        assert_eq!(eval("!(name == false)", &modal), json!(true));
+        assert_eq!(eval("!name == true", &modal), json!(true));
+    }
+
+    #[test]
+    fn test_expr_coalesce() {
+        let modal = json!({"nickname": null, "name": "Al", "user": {"address": null}});
+
+        assert_eq!(
+            eval("nickname ?? name ?? \"Anonymous\"", &modal),
+            json!("Al")
+        );
+        assert_eq!(
+            eval("missing ?? nickname ?? 'Anonymous'", &modal),
+            json!("Anonymous")
+        );
+        assert_eq!(eval("user.address?.city ?? 'n/a'", &modal), json!("n/a"));
+        assert_eq!(eval("name|upper ?? 'x'", &modal), json!("AL"));
+        assert_eq!(eval("nickname ?? false || true", &modal), json!(true));
+        assert!(parse("name ??").is_err());
+    }
+
+    #[test]
+    fn test_expr_arithmetic() {
+        let modal =
+            json!({"price": 2.5, "qty": 4, "page": 3, "total": 7, "name": "Al", "page-1": "key"});
+
+        assert_eq!(eval("price * qty", &modal), json!(10.0));
+        assert_eq!(eval("page + 1", &modal), json!(4));
+        assert_eq!(eval("page - 1", &modal), json!(2));
+        assert_eq!(eval("page -1", &modal), json!(2));
+        assert_eq!(eval("page-1", &modal), json!("key"));
+        assert_eq!(eval("page - -1", &modal), json!(4));
+        assert_eq!(eval("total / 2", &modal), json!(3));
+        assert_eq!(eval("total / 2.0", &modal), json!(3.5));
+        assert_eq!(eval("total % page", &modal), json!(1));
+        assert_eq!(eval("1 + 2 * (3 - 1)", &modal), json!(5));
+        assert_eq!(
+            eval("qty * page > 10 && page + 1 == 4", &modal),
+            json!(true)
+        );
+        assert_eq!(
+            eval("\"Hi \" + name|upper + \"!\"", &modal),
+            json!("Hi AL!")
+        );
+        assert_eq!(eval("\"page\" + page", &modal), json!("page3"));
+        assert_eq!(eval("missing + 1", &modal), json!(null));
+    }
+
+    #[test]
+    fn test_expr_arithmetic_errors() {
+        let modal = json!({"zero": 0, "tags": ["a"]});
+        let eval_err = |src: &str| parse(src).unwrap().eval(&ModalScope(&modal));
+
+        assert_eq!(
+            eval_err("1 / zero"),
+            Err(RenderError::Arithmetic {
+                message: String::from("division by zero")
+            })
+        );
+        assert!(eval_err("1.5 % zero").is_err());
+        assert!(eval_err("tags * 2").is_err());
+        assert!(eval_err("\"a\" - \"b\"").is_err());
+        assert!(eval_err("9223372036854775807 + 1").is_err());
+        assert!(parse("1 +").is_err());
+    }
+
+    #[test]
+    fn test_expr_indexed_path() {
+        let modal = json!({"users": [{"age": 20}, {"age": 40}]});
+
+        assert_eq!(eval("users[1].age >= 40", &modal), json!(true));
+    }
+
+    #[test]
+    fn test_expr_invalid() {
+        assert!(parse("a &&").is_err());
+        assert!(parse("(a || b").is_err());
+        assert!(parse("a b").is_err());
+        assert!(parse("name == \"open").is_err());
+        assert!(parse("a # b").is_err());
+        assert!(parse("a|").is_err());
+        assert!(parse("a|truncate(1").is_err());
+    }
+
+    #[test]
+    fn test_expr_filters() {
+        let modal = json!({"name": "  alice  ", "tags": ["a", "b"]});
+
+        assert_eq!(eval("name|trim|upper", &modal), json!("ALICE"));
+        assert_eq!(eval("tags|join(\", \")", &modal), json!("a, b"));
+        assert_eq!(eval("tags|length > 1", &modal), json!(true));
+        assert_eq!(eval("missing|default(name|trim)", &modal), json!("alice"));
+    }
+
+    #[test]
+    fn test_expr_unknown_filter() {
+        assert_eq!(
+            parse("name|shout"),
+            Err(TemplateErrorKind::UnknownFilter(String::from("shout")))
+        );
+        assert!(matches!(
+            parse("users[x]"),
+            Err(TemplateErrorKind::BadIndex(_))
+        ));
+    }
+}
@@ -0,0 +1,399 @@
+use super::path::{Path, display_string};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+
+/// A filter registered on an `Engine`, used in expressions as `value|name` or `value|name(args)`.
+///
+/// # Examples
+///
+/// ```
+/// struct Badge;
+///
+/// impl TemplateFilter for Badge {
+///     fn apply(&self, val: &Value, _args: &[Value]) -> Result<Value, String> {
+///         match val.as_str() {
+///             Some("active") => Ok(Value::from("badge-green")),
+///             Some(_) => Ok(Value::from("badge-grey")),
+///             None => Err(String::from("expected a status string")),
+///         }
+///     }
+/// }
+///
+/// let engine = Engine::new().with_filter("badge", Badge);
+/// ```
+pub trait TemplateFilter: Send + Sync {
+    /// Filters the value, along with the arguments the filter was given in the template.
+    fn apply(&self, val: &Value, args: &[Value]) -> Result<Value, String>;
+}
+
+impl<F> TemplateFilter for F
+where
+    F: Fn(&Value, &[Value]) -> Result<Value, String> + Send + Sync,
+{
+    fn apply(&self, val: &Value, args: &[Value]) -> Result<Value, String> {
+        self(val, args)
+    }
+}
+
+/// A built in filter. Receives the value being filtered along with the filter's arguments.
+pub type FilterFn = fn(&Value, &[Value]) -> Value;
+
+/// Appended to a string that `truncate` shortens, unless another ending is supplied.
+const TRUNCATE_END: &str = "...";
+
+/// Finds the built in filter with the supplied name.
+pub fn builtin(name: &str) -> Option<FilterFn> {
+    let filter: FilterFn = match name {
+        "upper" => upper,
+        "lower" => lower,
+        "trim" => trim,
+        "truncate" => truncate,
+        "default" => default,
+        "join" => join,
+        "length" => length,
+        "sort_by" => sort_by,
+        "where" => where_eq,
+        "group_by" => group_by,
+        "reverse" => reverse,
+        "first" => first,
+        "last" => last,
+        "unique" => unique,
+        _ => return None,
+    };
+
+    Some(filter)
+}
+
+/// `upper` converts the value to uppercase.
+fn upper(val: &Value, _args: &[Value]) -> Value {
+    Value::String(display_string(val).to_uppercase())
+}
+
+/// `lower` converts the value to lowercase.
+fn lower(val: &Value, _args: &[Value]) -> Value {
+    Value::String(display_string(val).to_lowercase())
+}
+
+/// `trim` removes leading and trailing whitespace.
+fn trim(val: &Value, _args: &[Value]) -> Value {
+    Value::String(display_string(val).trim().to_string())
+}
+
+/// `truncate(length, end)` shortens the value to at most `length` characters, including `end`, which defaults
+/// to `...`. Values that already fit are left alone.
+fn truncate(val: &Value, args: &[Value]) -> Value {
+    let text = display_string(val);
+    let Some(max_len) = args.first().and_then(Value::as_u64).map(|len| len as usize) else {
+        return Value::String(text);
+    };
+
+    if text.chars().count() <= max_len {
+        return Value::String(text);
+    }
+
+    let end = args.get(1).map(display_string);
+    let end = end.as_deref().unwrap_or(TRUNCATE_END);
+    let keep = max_len.saturating_sub(end.chars().count());
+
+    let mut short: String = text.chars().take(keep).collect();
+    short.push_str(end);
+    Value::String(short)
+}
+
+/// `default(value)` replaces a missing or null value.
+fn default(val: &Value, args: &[Value]) -> Value {
+    match val {
+        Value::Null => args.first().cloned().unwrap_or_default(),
+        _ => val.clone(),
+    }
+}
+
+/// `join(separator)` joins the values of a collection, with no separator unless one is supplied.
+fn join(val: &Value, args: &[Value]) -> Value {
+    let Value::Array(arr) = val else {
+        return val.clone();
+    };
+    let separator = args.first().map(display_string).unwrap_or_default();
+
+    Value::String(
+        arr.iter()
+            .map(display_string)
+            .collect::<Vec<String>>()
+            .join(&separator),
+    )
+}
+
+/// `length` counts the characters of a string, or the values of a collection or object.
+fn length(val: &Value, _args: &[Value]) -> Value {
+    let len = match val {
+        Value::String(val) => val.chars().count(),
+        Value::Array(val) => val.len(),
+        Value::Object(val) => val.len(),
+        _ => 0,
+    };
+
+    Value::from(len)
+}
+
+/// `sort_by(key)` sorts a collection by the value at `key` in each item, such as `sort_by("profile.age")`.
+/// Without a key, the items themselves are compared. Items that are equal keep their order.
+fn sort_by(val: &Value, args: &[Value]) -> Value {
+    let Value::Array(arr) = val else {
+        return val.clone();
+    };
+    let key = args.first().and_then(Value::as_str);
+
+    let mut sorted = arr.clone();
+    sorted.sort_by(|left, right| compare_values(item_key(left, key), item_key(right, key)));
+    Value::Array(sorted)
+}
+
+/// `where(key, value)` keeps the items of a collection whose value at `key` equals `value`, or is true when no
+/// value is supplied.
+fn where_eq(val: &Value, args: &[Value]) -> Value {
+    let (Value::Array(arr), Some(key)) = (val, args.first().and_then(Value::as_str)) else {
+        return val.clone();
+    };
+    let expected = args.get(1).unwrap_or(&Value::Bool(true));
+
+    Value::Array(
+        arr.iter()
+            .filter(|item| compare_values(item_key(item, Some(key)), expected).is_eq())
+            .cloned()
+            .collect(),
+    )
+}
+
+/// `group_by(key)` groups the items of a collection by their value at `key`, into an object of collections
+/// keyed by that value. Loop over it with `@for:(name, items) in ...;`.
+fn group_by(val: &Value, args: &[Value]) -> Value {
+    let (Value::Array(arr), Some(key)) = (val, args.first().and_then(Value::as_str)) else {
+        return val.clone();
+    };
+    let mut groups = Map::new();
+
+    for item in arr {
+        let group = groups
+            .entry(display_string(item_key(item, Some(key))))
+            .or_insert_with(|| Value::Array(vec![]));
+
+        if let Value::Array(group) = group {
+            group.push(item.clone());
+        }
+    }
+
+    Value::Object(groups)
+}
+
+/// `reverse` reverses a collection, or the characters of a string.
+fn reverse(val: &Value, _args: &[Value]) -> Value {
+    match val {
+        Value::Array(arr) => Value::Array(arr.iter().rev().cloned().collect()),
+        Value::String(val) => Value::String(val.chars().rev().collect()),
+        _ => val.clone(),
+    }
+}
+
+/// `first` is the first value of a collection, or the first character of a string.
+fn first(val: &Value, _args: &[Value]) -> Value {
+    match val {
+        Value::Array(arr) => arr.first().cloned().unwrap_or_default(),
+        Value::String(val) => val
+            .chars()
+            .next()
+            .map(|ch| Value::from(ch.to_string()))
+            .unwrap_or_default(),
+        _ => Value::Null,
+    }
+}
+
+/// `last` is the last value of a collection, or the last character of a string.
+fn last(val: &Value, _args: &[Value]) -> Value {
+    match val {
+        Value::Array(arr) => arr.last().cloned().unwrap_or_default(),
+        Value::String(val) => val
+            .chars()
+            .next_back()
+            .map(|ch| Value::from(ch.to_string()))
+            .unwrap_or_default(),
+        _ => Value::Null,
+    }
+}
+
+/// `unique` removes repeated values from a collection, keeping the first of each.
+fn unique(val: &Value, _args: &[Value]) -> Value {
+    let Value::Array(arr) = val else {
+        return val.clone();
+    };
+    let mut seen: Vec<&Value> = vec![];
+
+    for item in arr {
+        if !seen.iter().any(|other| compare_values(other, item).is_eq()) {
+            seen.push(item);
+        }
+    }
+
+    Value::Array(seen.into_iter().cloned().collect())
+}
+
+/// The value at `key` within an item, or the item itself without a key. Missing keys are null.
+fn item_key<'a>(item: &'a Value, key: Option<&str>) -> &'a Value {
+    const NULL: &Value = &Value::Null;
+
+    match key.map(Path::parse) {
+        None => item,
+        Some(Ok(path)) => path.resolve(item).unwrap_or(NULL),
+        Some(Err(_)) => NULL,
+    }
+}
+
+/// Orders values for sorting. Numbers compare by value, so `1` equals `1.0`. Values of different types are
+/// ordered null, bool, number, string, collection, object.
+fn compare_values(left: &Value, right: &Value) -> Ordering {
+    let rank = |val: &Value| match val {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    };
+
+    match (left, right) {
+        (Value::Bool(left), Value::Bool(right)) => left.cmp(right),
+        (Value::Number(left), Value::Number(right)) => left
+            .as_f64()
+            .zip(right.as_f64())
+            .and_then(|(left, right)| left.partial_cmp(&right))
+            .unwrap_or(Ordering::Equal),
+        (Value::String(left), Value::String(right)) => left.cmp(right),
+        (Value::Array(_), Value::Array(_)) | (Value::Object(_), Value::Object(_))
+            if left == right =>
+        {
+            Ordering::Equal
+        }
+        _ => rank(left).cmp(&rank(right)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply(name: &str, val: Value, args: &[Value]) -> Value {
+        builtin(name).unwrap()(&val, args)
+    }
+
+    #[test]
+    fn test_filter_case() {
+        assert_eq!(apply("upper", json!("Alice"), &[]), json!("ALICE"));
+        assert_eq!(apply("lower", json!("Alice"), &[]), json!("alice"));
+        assert_eq!(apply("trim", json!("  a b "), &[]), json!("a b"));
+    }
+
+    #[test]
+    fn test_filter_truncate() {
+        assert_eq!(
+            apply("truncate", json!("Hello world"), &[json!(8)]),
+            json!("Hello...")
+        );
+        assert_eq!(
+            apply("truncate", json!("Hello world"), &[json!(6), json!("~")]),
+            json!("Hello~")
+        );
+        assert_eq!(
+            apply("truncate", json!("Hello"), &[json!(5)]),
+            json!("Hello")
+        );
+    }
+
+    #[test]
+    fn test_filter_default() {
+        assert_eq!(apply("default", json!(null), &[json!("n/a")]), json!("n/a"));
+        assert_eq!(apply("default", json!(""), &[json!("n/a")]), json!(""));
+    }
+
+    #[test]
+    fn test_filter_join_and_length() {
+        let val = json!(["a", "b", 3]);
+
+        assert_eq!(apply("join", val.clone(), &[json!(", ")]), json!("a, b, 3"));
+        assert_eq!(apply("length", val, &[]), json!(3));
+        assert_eq!(apply("length", json!("héllo"), &[]), json!(5));
+        assert_eq!(apply("length", json!(null), &[]), json!(0));
+    }
+
+    #[test]
+    fn test_filter_sort_by() {
+        let users = json!([
+            {"name": "Cy", "age": 30},
+            {"name": "Al", "age": 25.5},
+            {"name": "Bo", "age": 30},
+            {"name": "Di"}
+        ]);
+
+        assert_eq!(
+            apply("sort_by", users.clone(), &[json!("name")]),
+            json!([
+                {"name": "Al", "age": 25.5},
+                {"name": "Bo", "age": 30},
+                {"name": "Cy", "age": 30},
+                {"name": "Di"}
+            ])
+        );
+        assert_eq!(
+            apply("sort_by", users, &[json!("age")])
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|user| user["name"].clone())
+                .collect::<Vec<Value>>(),
+            vec![json!("Di"), json!("Al"), json!("Cy"), json!("Bo")]
+        );
+        assert_eq!(apply("sort_by", json!([3, 1, 2]), &[]), json!([1, 2, 3]));
+    }
+
+    #[test]
+    fn test_filter_where_and_group_by() {
+        let users = json!([
+            {"name": "Al", "role": "admin", "active": true},
+            {"name": "Bo", "role": "user", "active": false},
+            {"name": "Cy", "role": "admin", "active": true}
+        ]);
+
+        assert_eq!(
+            apply("where", users.clone(), &[json!("active")]),
+            json!([users[0], users[2]])
+        );
+        assert_eq!(
+            apply("where", users.clone(), &[json!("role"), json!("user")]),
+            json!([users[1]])
+        );
+        assert_eq!(
+            apply("group_by", users.clone(), &[json!("role")]),
+            json!({"admin": [users[0], users[2]], "user": [users[1]]})
+        );
+    }
+
+    #[test]
+    fn test_filter_reverse_first_last_unique() {
+        let val = json!([1, "a", 1.0, "a", 2]);
+
+        assert_eq!(
+            apply("reverse", val.clone(), &[]),
+            json!([2, "a", 1.0, "a", 1])
+        );
+        assert_eq!(apply("reverse", json!("abc"), &[]), json!("cba"));
+        assert_eq!(apply("first", val.clone(), &[]), json!(1));
+        assert_eq!(apply("last", val.clone(), &[]), json!(2));
+        assert_eq!(apply("first", json!([]), &[]), json!(null));
+        assert_eq!(apply("last", json!("héllo"), &[]), json!("o"));
+        assert_eq!(apply("unique", val, &[]), json!([1, "a", 2]));
+    }
+
+    #[test]
+    fn test_filter_unknown() {
+        assert!(builtin("shout").is_none());
+    }
+}
@@ -0,0 +1,123 @@
+use serde_json::Value;
+
+/// A function registered on an `Engine`, used in expressions as `name(args)`.
+///
+/// # Examples
+///
+/// ```
+/// let engine = Engine::new().with_function("plan_name", |args: &[Value]| {
+///     match args.first().and_then(Value::as_u64) {
+///         Some(1) => Ok(Value::from("Starter")),
+///         Some(2) => Ok(Value::from("Pro")),
+///         _ => Err(String::from("unknown plan id")),
+///     }
+/// });
+/// ```
+pub trait TemplateFunction: Send + Sync {
+    /// Calls the function with the arguments it was given in the template.
+    fn call(&self, args: &[Value]) -> Result<Value, String>;
+}
+
+impl<F> TemplateFunction for F
+where
+    F: Fn(&[Value]) -> Result<Value, String> + Send + Sync,
+{
+    fn call(&self, args: &[Value]) -> Result<Value, String> {
+        self(args)
+    }
+}
+
+/// A built in function. Receives the arguments it was given in the template.
+pub type FunctionFn = fn(&[Value]) -> Result<Value, String>;
+
+/// The most values `range` may produce, so a template cannot exhaust memory with a huge range.
+const MAX_RANGE_LEN: i64 = 100_000;
+
+/// Finds the built in function with the supplied name.
+pub fn builtin(name: &str) -> Option<FunctionFn> {
+    let function: FunctionFn = match name {
+        "range" => range,
+        _ => return None,
+    };
+
+    Some(function)
+}
+
+/// `range(start, end, step)` counts from `start` up to, but not including, `end`. `range(end)` counts from zero,
+/// and `step` defaults to 1. A negative step counts down.
+fn range(args: &[Value]) -> Result<Value, String> {
+    let ints = args
+        .iter()
+        .map(|arg| {
+            arg.as_i64()
+                .ok_or_else(|| format!("expected an integer, found {}", arg))
+        })
+        .collect::<Result<Vec<i64>, String>>()?;
+
+    let (start, end, step) = match ints[..] {
+        [end] => (0, end, 1),
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step),
+        _ => return Err(String::from("expected 1 to 3 arguments")),
+    };
+
+    if step == 0 {
+        return Err(String::from("step cannot be zero"));
+    }
+
+    // counted in i128, so ranges spanning the whole of i64 cannot overflow.
+    let span = i128::from(end) - i128::from(start);
+    let step_len = i128::from(step).abs();
+    let len = if span.signum() == i128::from(step.signum()) {
+        (span.abs() + step_len - 1) / step_len
+    } else {
+        0
+    };
+
+    if len > i128::from(MAX_RANGE_LEN) {
+        return Err(format!("range is longer than {} values", MAX_RANGE_LEN));
+    }
+
+    Ok(Value::Array(
+        (0..len as i64)
+            .map(|idx| Value::from(start + idx * step))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+        builtin(name).unwrap()(args)
+    }
+
+    #[test]
+    fn test_function_range() {
+        assert_eq!(
+            call("range", &[json!(1), json!(5)]),
+            Ok(json!([1, 2, 3, 4]))
+        );
+        assert_eq!(call("range", &[json!(3)]), Ok(json!([0, 1, 2])));
+        assert_eq!(
+            call("range", &[json!(0), json!(7), json!(3)]),
+            Ok(json!([0, 3, 6]))
+        );
+        assert_eq!(
+            call("range", &[json!(5), json!(0), json!(-2)]),
+            Ok(json!([5, 3, 1]))
+        );
+        assert_eq!(call("range", &[json!(5), json!(1)]), Ok(json!([])));
+    }
+
+    #[test]
+    fn test_function_range_errors() {
+        assert!(call("range", &[]).is_err());
+        assert!(call("range", &[json!("a")]).is_err());
+        assert!(call("range", &[json!(0), json!(5), json!(0)]).is_err());
+        assert!(call("range", &[json!(0), json!(i64::MAX)]).is_err());
+        assert!(builtin("shout").is_none());
+    }
+}
@@ -1,9 +1,13 @@
-use serde_json::Value;
-
-const MAX_TOKEN_LEN: usize = 1000;
+use super::error::TemplateError;
+use super::template::Template;
 
 /// - Parse and process the modal token values found in the supplied String. A new String is returned as a result.
 ///
+/// - The String is compiled into a `Template` on every call. Pages rendered repeatedly should compile once and use `Template::render`.
+///
+/// - An error in the template is logged and an empty String returned, so a broken template renders a blank page.
+///   Prefer `try_process_string`, which returns the error.
+///
 /// - The format of tokens are as follows: \@\[token type\]:\[value key\];
 ///
 /// Example: @value:name;
@@ -13,17 +17,69 @@ const MAX_TOKEN_LEN: usize = 1000;
 ///
 /// Example: @value:names\[1\].first;
 ///
+/// - Negative indexes count back from the end of a collection, and \[start..end\] takes a slice of a collection or
+///   string, up to but not including end. Either end of a slice can be left out.
+///
+/// Example: @value:names\[-1\]; @for:user in users\[0..5\];{...}
+///
 ///
 ///
 /// - Valid token types are;
 ///
-/// 1) value       - Displays the value of the key provided.
+/// 1) value       - Displays the value of the key provided. The value is HTML escaped. Filters can be applied
+///    with `|`, left to right: upper, lower, trim, truncate(length), default(value), join(separator) and length.
+///    For collections there are also sort_by(key), where(key, value), group_by(key), reverse, first, last and
+///    unique.
+///
+/// Example: @value:name|trim|truncate(20); @value:str_vec|join(", ");
+///
+///    `??` falls back to the next value when a value is null or missing, and `?.` makes a path null instead of
+///    missing when the value before it is null, such as an `Option` field that is `None`.
+///
+/// Example: @value:nickname ?? name ?? "Anonymous"; @value:user.address?.city;
+///
+///    `+`, `-`, `*`, `/` and `%` do arithmetic, keeping integers as integers unless a float is involved, and `+`
+///    joins strings. A `-` between the letters of a key is part of the key, so subtraction needs whitespace
+///    before the `-`.
+///
+/// Example: @value:user-name; @value:price * qty; Page @value:page + 1; of @value:pages; @value:"Hi " + name;
+///
+///    Filters and functions of your own can be registered with `Engine::with_filter` and `Engine::with_function`,
+///    and templates using them compiled with `Engine::compile`.
+///
+/// Example: @value:status|badge; @value:plan_name(plan_id);
+///
+/// 2) if          - Displays the contents inside of the {} if the provided condition is true. By default only a true
+///    bool is true, see `Engine::with_truthiness` for JavaScript like rules. Conditions can use `!`, `==`, `!=`,
+///    `<`, `<=`, `>`, `>=`, `&&`, `||`, parentheses and string, number, bool or null literals.
 ///
-/// 2) if          - Displays the contents inside of the {} if the provided value is a true bool.
+/// Example: @if:!user.is_admin && (count > 0 || role == "owner");
 ///
-/// 3) for         - Repeats the contents inside of the {} for each value within the provided collection value.
+/// 3) for         - Repeats the contents inside of the {} for each value within the provided collection value. Naming
+///    a loop variable with `[name] in [key]` makes each value available to every other token by that name.
 ///
-/// 4) forvalue    - Displays the value of the key provided, with the value originating from a for loop. The first element of the key must be an index of the loop level.
+/// Example: @for:user in users;{ @value:user.name; @for:child in user.user_vec;{ @value:child.name; } }
+///
+///    Objects can be looped over too, in key order. `([key], [value]) in [key]` names both the key and the value,
+///    and on a collection the key is the index.
+///
+/// Example: @for:(name, setting) in settings;{ @value:name; = @value:setting; }
+///
+///    Collection filters change what is looped over. group_by makes an object of collections, looped over by
+///    name.
+///
+/// Example: @for:user in users|where("active", true)|sort_by("name");{...} @for:(role, members) in users|group_by("role");{...}
+///
+///    The built in `range(start, end)` function counts from start up to, but not including, end.
+///
+/// Example: @for:page in range(1, page_count + 1);{ @value:page; }
+///
+///    Inside a for, `loop` holds the innermost loop's metadata: `loop.index` (starting at 1), `loop.index0`
+///    (starting at 0), `loop.first`, `loop.last`, `loop.length` and `loop.parent` for the enclosing loop.
+///
+/// Example: @for:user in users;{ @value:loop.index; of @value:loop.length;@if:!loop.last;{, } }
+///
+/// 4) forvalue    - Displays the value of the key provided, with the value originating from a for loop. The first element of the key must be an index of the loop level. The value is HTML escaped.
 ///
 /// Example: @forvalue:0.name;
 ///
@@ -35,6 +91,71 @@ const MAX_TOKEN_LEN: usize = 1000;
 ///
 /// Example: @forfor:2.user_groups;
 ///
+/// 7) raw         - Displays the value of the key provided without escaping. Only use this for trusted markup.
+///
+/// Example: @raw:footer_html;
+///
+/// 8) elseif, forelseif, else - Chained directly after the {} of an if or forif, with only whitespace between. The
+///    first branch whose value is a true bool is displayed, otherwise the contents of the else are displayed.
+///
+/// Example: @if:is_admin;{...}@elseif:is_owner;{...}@else{...}
+///
+/// 9) empty       - Chained directly after the {} of a for or forfor, with only whitespace between. The contents are
+///    displayed when the collection value is empty or missing.
+///
+/// Example: @for:user in users;{...}@empty{ No results }
+///
+/// 10) include    - Displays another template, loaded from the directory set with `Engine::with_template_root`. The
+///     included template sees the same modal and loop variables, and may not include itself.
+///
+/// Example: @include:partials/header.html;
+///
+/// 11) extends, block, parent - A template can extend a layout with @extends, replacing the layout's @block
+///     sections with its own blocks of the same name. Anything outside of the template's blocks is ignored.
+///     Within a block, @parent; displays the layout's content of the block. A block is escaped for where the
+///     layout's block sits, such as inside a `<script>`, and must end in the same part of the HTML as the layout's.
+///
+/// Example: layout.html: <main>@block:content;{}</main> page.html: @extends:layout.html;@block:content;{...}
+///
+/// 12) macro, call - A macro is a reusable part of the template with parameters, displayed with @call. Inside a
+///     macro only its parameters and the modal are available, and macros can call themselves. A macro is written
+///     as HTML text, so @call can only be used in text, outside of tags, scripts and styles.
+///
+/// Example: @macro:user_card(user, compact);{...} @call:user_card(user, true);
+///
+/// - Blocks can be closed with end tags instead of braces, by starting the template with @syntax:endtags; or with
+///   `Engine::with_block_syntax`. Braces inside the blocks are then plain text, which suits inline CSS and
+///   JavaScript. Each block ends with @endif;, @endfor;, @endblock;, @endmacro; or @end[token type]; for
+///   registered tokens, and @else; and @empty; separate the parts of a block.
+///
+/// Example: @if:user.is_admin;<script>if (a) { b(); }</script>@else;...@endif;
+///
+/// - Comments are written as @* ... *@ and are left out of the output. Everything between @verbatim; and
+///   @endverbatim; is displayed as is, without tokens, braces or backslashes being processed.
+///
+/// Example: @* shown to admins only *@ @verbatim;<p>{{ client.side }} me@example.com</p>@endverbatim;
+///
+/// - A `-` directly after the @ of a token drops the whitespace before the token, and a `-` directly after its ;
+///   drops the whitespace after it, including the start of a block's body. `Engine::with_trim_blocks` leaves
+///   lines holding nothing but block tags out of the output.
+///
+/// Example: <ul>@-for:user in users;-{ <li>@value:user;</li> }</ul>
+///
+/// - Token types of your own can be registered with `Engine::with_token` for inline tokens, or
+///   `Engine::with_block_token` for tokens with a {} body, such as @permission:admin;{...}.
+///
+/// - Keys missing from the modal and indexes past the end of a collection display as empty. With
+///   `Engine::with_undefined` they can fail the render with an error naming the path, or display a placeholder.
+///
+/// - forvalue, forif, forfor and forelseif are kept for compatibility. Named loop variables are preferred, since they
+///   do not depend on how deeply the loop is nested.
+///
+/// - Escaped values follow the HTML context they are written into. Text and quoted attributes are HTML escaped,
+///   URL attributes such as href are percent encoded and only allow http, https and mailto schemes, values in
+///   `<script>` or on* attributes are written as JavaScript literals, and values in `<style>` or style attributes
+///   only allow simple keywords, numbers and colors. Every branch of an @if, and the body of a @for, must end in
+///   the same part of the HTML it starts in, so an @if cannot open a tag or `<script>` that its @else does not.
+///
 /// # Examples
 ///
 /// ```
@@ -62,546 +183,38 @@ const MAX_TOKEN_LEN: usize = 1000;
 ///     test_bool: true
 /// };
 ///
-/// let result = html_modal::process_string(&html, &user);
+/// let result = html_modal::try_process_string(&html, &user).unwrap();
 /// ```
-pub fn process_string<T: serde::ser::Serialize>(html: &String, modal: &T) -> String {
-    let json_value: Value = serde_json::to_value(&modal).unwrap_or_default();
-    let mut foreach_vals: Vec<Option<Value>> = vec![];
-
-    String::from_utf8(parse_raw(html, &json_value, &mut foreach_vals)).unwrap_or_default()
+#[allow(dead_code)]
+#[deprecated(
+    note = "returns an empty String when the template has an error; use `try_process_string`"
+)]
+pub fn process_string<T: serde::ser::Serialize>(html: &str, modal: &T) -> String {
+    try_process_string(html, modal).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        String::new()
+    })
 }
 
-fn parse(str: &String, modal: &Value, foreach_modal: &mut Vec<Option<Value>>) -> String {
-    String::from_utf8(parse_raw(str, modal, foreach_modal)).unwrap_or_default()
-}
-
-fn parse_raw(str: &String, modal: &Value, foreach_modal: &mut Vec<Option<Value>>) -> Vec<u8> {
-    let mut ret_vec: Vec<u8> = vec![];
-
-    // size of returned string is likely longer, but this will allow for a large amount
-    // of inserting without re-allocation.
-    ret_vec.reserve(str.len());
-
-    let bytes = str.as_bytes();
-    let bytes_len = bytes.len();
-
-    let mut i = 0;
-    while i < bytes_len {
-        let ch = bytes[i];
-
-        // skip escape characters. \@ tokens will be displayed in raw text.
-        if ch == b'\\' {
-            i += 1;
-
-            if bytes_len > i {
-                let ch = bytes[i];
-                ret_vec.push(ch);
-                i += 1;
-                continue;
-            } else {
-                break;
-            }
-        }
-
-        if ch == b'@' {
-            i += 1;
-
-            if let Ok(token_type) = parse_token_type(bytes, bytes_len, &mut ret_vec, &mut i)
-                && let Ok(token_key) = parse_token_key(bytes, bytes_len, &mut ret_vec, &mut i)
-            {
-                match token_type.to_lowercase().as_str() {
-                    "value" => {
-                        parse_value(modal, &mut ret_vec, &token_key);
-                    }
-                    "forvalue" => {
-                        parse_forvalue(foreach_modal, &mut ret_vec, &token_key);
-                    }
-                    "for" => {
-                        parse_for(
-                            modal,
-                            foreach_modal,
-                            &mut ret_vec,
-                            bytes,
-                            bytes_len,
-                            &mut i,
-                            &token_key,
-                        );
-                    }
-                    "forfor" => {
-                        parse_forfor(
-                            modal,
-                            foreach_modal,
-                            &mut ret_vec,
-                            bytes,
-                            bytes_len,
-                            &mut i,
-                            &token_key,
-                        );
-                    }
-                    "if" => {
-                        parse_if(
-                            modal,
-                            foreach_modal,
-                            &mut ret_vec,
-                            bytes,
-                            bytes_len,
-                            &mut i,
-                            &token_key,
-                        );
-                    }
-                    "forif" => {
-                        parse_forif(
-                            modal,
-                            foreach_modal,
-                            &mut ret_vec,
-                            bytes,
-                            bytes_len,
-                            &mut i,
-                            &token_key,
-                        );
-                    }
-                    _ => {
-                        ret_vec.extend_from_slice(
-                            format!("@{}:{};", token_type, token_key).as_bytes(),
-                        );
-                    }
-                }
-            }
-        } else {
-            ret_vec.push(ch);
-            i += 1;
-        }
-    }
-
-    ret_vec
-}
-
-fn parse_token_type(
-    bytes: &[u8],
-    bytes_len: usize,
-    ret_vec: &mut Vec<u8>,
-    i: &mut usize,
-) -> Result<String, String> {
-    let mut token_type: Vec<u8> = vec![];
-    while *i < bytes_len && bytes[*i] != b':' {
-        let byte = bytes[*i];
-
-        token_type.push(byte);
-        *i += 1;
-
-        if token_type.len() >= MAX_TOKEN_LEN || byte == b' ' {
-            ret_vec.extend_from_slice(&token_type);
-            return Err(String::from("Token too long or invalid!"));
-        }
-    }
-
-    if *i < bytes_len && bytes[*i] == b':' {
-        *i += 1;
-    }
-
-    if *i >= bytes_len {
-        ret_vec.extend_from_slice(&token_type);
-        return Err(String::from("Token too long or invalid!"));
-    }
-
-    Ok(String::from_utf8(token_type).unwrap_or_default())
-}
-
-fn parse_token_key(
-    bytes: &[u8],
-    bytes_len: usize,
-    ret_vec: &mut Vec<u8>,
-    i: &mut usize,
-) -> Result<String, String> {
-    let mut token_key: Vec<u8> = vec![];
-    while *i < bytes_len && bytes[*i] != b';' && bytes[*i] != b'{' {
-        let byte = bytes[*i];
-
-        token_key.push(byte);
-        *i += 1;
-
-        if token_key.len() >= MAX_TOKEN_LEN || byte == b' ' {
-            ret_vec.extend_from_slice(&token_key);
-            return Err(String::from("Token too long or invalid!"));
-        }
-    }
-
-    if *i < bytes_len && bytes[*i] == b';' {
-        *i += 1;
-    }
-
-    if *i >= bytes_len {
-        ret_vec.extend_from_slice(&token_key);
-        return Err(String::from("Token too long or invalid!"));
-    }
-
-    Ok(String::from_utf8(token_key).unwrap_or_default())
-}
-
-fn parse_value(modal: &Value, ret_vec: &mut Vec<u8>, token_key: &String) {
-    let val = get_display_string(modal, token_key);
-    ret_vec.extend_from_slice(val.as_bytes());
-}
-
-fn parse_forvalue(
-    foreach_modal: &mut Vec<Option<Value>>,
-    ret_vec: &mut Vec<u8>,
-    token_key: &String,
-) {
-    let mut parts = token_key.splitn(2, '.');
-
-    if let (Some(idx_str), key) = (parts.next(), parts.next()) {
-        if let Ok(idx) = idx_str.parse::<usize>() {
-            if let Some(Some(fe_mod)) = foreach_modal.get(idx) {
-                let val = get_display_string(fe_mod, &key.unwrap_or_default().to_string());
-                ret_vec.extend_from_slice(val.as_bytes());
-            }
-        }
-    }
-}
-
-fn parse_for(
-    modal: &Value,
-    foreach_modal: &mut Vec<Option<Value>>,
-    ret_vec: &mut Vec<u8>,
-    bytes: &[u8],
-    bytes_len: usize,
-    i: &mut usize,
-    token_key: &String,
-) {
-    while *i < bytes_len && bytes[*i] != b'{' {
-        *i += 1;
-    }
-
-    if *i < bytes_len && bytes[*i] == b'{' {
-        *i += 1;
-        let mut brace_count = 1;
-        let start = *i;
-
-        while *i < bytes_len && brace_count > 0 {
-            if bytes[*i] == b'{' {
-                brace_count += 1;
-            }
-            if bytes[*i] == b'}' {
-                brace_count -= 1;
-            }
-            *i += 1;
-        }
-
-        let end = *i - 1;
-        let inner = String::from_utf8(bytes[start..end].to_vec()).unwrap_or_default();
-        let disp_val = get_display_value(modal, token_key);
-
-        if let Some(arr) = disp_val.as_array() {
-            for val in arr.iter() {
-                foreach_modal.push(Some(val.clone()));
-                let parsed = parse_raw(&inner, modal, foreach_modal);
-                ret_vec.extend(parsed);
-                foreach_modal.pop();
-            }
-        }
-    }
-}
-
-fn parse_forfor(
-    modal: &Value,
-    foreach_modal: &mut Vec<Option<Value>>,
-    ret_vec: &mut Vec<u8>,
-    bytes: &[u8],
-    bytes_len: usize,
-    i: &mut usize,
-    token_key: &String,
-) {
-    let mut parts = token_key.splitn(2, '.');
-
-    if let (Some(idx_str), Some(key)) = (parts.next(), parts.next()) {
-        if let Ok(idx) = idx_str.parse::<usize>() {
-            if let Some(Some(fe_mod)) = foreach_modal.get(idx) {
-                while *i < bytes_len && bytes[*i] != b'{' {
-                    *i += 1;
-                }
-
-                if *i < bytes_len && bytes[*i] == b'{' {
-                    *i += 1;
-                    let mut brace_count = 1;
-                    let start = *i;
-
-                    while *i < bytes_len && brace_count > 0 {
-                        if bytes[*i] == b'{' {
-                            brace_count += 1;
-                        }
-                        if bytes[*i] == b'}' {
-                            brace_count -= 1;
-                        }
-                        *i += 1;
-                    }
-
-                    let end = *i - 1;
-                    let inner = String::from_utf8(bytes[start..end].to_vec()).unwrap_or_default();
-                    let disp_val = get_display_value(fe_mod, &key.to_string());
-
-                    if let Some(arr) = disp_val.as_array() {
-                        for val2 in arr.iter() {
-                            foreach_modal.push(Some(val2.clone()));
-                            let parsed = parse_raw(&inner, modal, foreach_modal);
-                            ret_vec.extend(parsed);
-                            foreach_modal.pop();
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
-
-fn parse_if(
-    modal: &Value,
-    foreach_modal: &mut Vec<Option<Value>>,
-    ret_vec: &mut Vec<u8>,
-    bytes: &[u8],
-    bytes_len: usize,
-    i: &mut usize,
-    token_key: &String,
-) {
-    while *i < bytes_len && bytes[*i] != b'{' {
-        *i += 1;
-    }
-
-    if *i < bytes_len && bytes[*i] == b'{' {
-        *i += 1;
-        let mut brace_count = 1;
-        let start = *i;
-
-        while *i < bytes_len && brace_count > 0 {
-            if bytes[*i] == b'{' {
-                brace_count += 1;
-            }
-            if bytes[*i] == b'}' {
-                brace_count -= 1;
-            }
-            *i += 1;
-        }
-
-        let end = *i - 1;
-        let inner = String::from_utf8(bytes[start..end].to_vec()).unwrap_or_default();
-        let disp_val = get_display_value(modal, token_key);
-
-        if disp_val.as_bool().unwrap_or(false) {
-            let parsed = parse_raw(&inner, modal, foreach_modal);
-            ret_vec.extend(parsed);
-        }
-    }
-}
-
-fn parse_forif(
-    modal: &Value,
-    foreach_modal: &mut Vec<Option<Value>>,
-    ret_vec: &mut Vec<u8>,
-    bytes: &[u8],
-    bytes_len: usize,
-    i: &mut usize,
-    token_key: &String,
-) {
-    let mut parts = token_key.splitn(2, '.');
-    if let (Some(idx_str), Some(key)) = (parts.next(), parts.next()) {
-        if let Ok(idx) = idx_str.parse::<usize>() {
-            if let Some(Some(fe_mod)) = foreach_modal.get(idx) {
-                while *i < bytes_len && bytes[*i] != b'{' {
-                    *i += 1;
-                }
-
-                if *i < bytes_len && bytes[*i] == b'{' {
-                    *i += 1;
-                    let mut brace_count = 1;
-                    let start = *i;
-
-                    while *i < bytes_len && brace_count > 0 {
-                        if bytes[*i] == b'{' {
-                            brace_count += 1;
-                        }
-                        if bytes[*i] == b'}' {
-                            brace_count -= 1;
-                        }
-                        *i += 1;
-                    }
-
-                    let end = *i - 1;
-                    let inner = String::from_utf8(bytes[start..end].to_vec()).unwrap_or_default();
-                    let disp_val = get_display_value(fe_mod, &key.to_string());
-
-                    if disp_val.as_bool().unwrap_or(false) {
-                        let parsed = parse_raw(&inner, modal, foreach_modal);
-                        ret_vec.extend(parsed);
-                    }
-                }
-            }
-        }
-    }
-}
-
-fn get_display_value(modal: &Value, attr_val: &String) -> Value {
-    let val_split = attr_val.split(".");
-    let mut disp_val = modal;
-
-    for val in val_split {
-        if disp_val.is_object() {
-            if val.contains("[") {
-                let mut index_split = val.split('[');
-                while let Some(key) = index_split.next() {
-                    if key.len() == 0 {
-                        continue;
-                    }
-
-                    if !key.ends_with(']') {
-                        disp_val = &disp_val[key];
-                    }
-
-                    match disp_val {
-                        Value::Array(arr) => {
-                            let index = key[0..key.len() - 1].parse::<usize>();
-
-                            match index {
-                                Ok(idx) => {
-                                    disp_val = &arr[idx];
-                                }
-                                Err(_) => {}
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            } else {
-                disp_val = &disp_val[val];
-            }
-        } else if disp_val.is_array() && val.contains("[") {
-            let mut index_split = val.split('[');
-            while let Some(key) = index_split.next() {
-                if key.len() == 0 {
-                    continue;
-                }
-
-                if !key.ends_with(']') {
-                    disp_val = &disp_val[key];
-                }
-
-                match disp_val {
-                    Value::Array(arr) => {
-                        let index = key[0..key.len() - 1].parse::<usize>();
-
-                        match index {
-                            Ok(idx) => {
-                                disp_val = &arr[idx];
-                            }
-                            Err(_) => {}
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        } else {
-            break;
-        }
-    }
-    disp_val.clone()
-}
-
-fn get_display_string(modal: &Value, attr_val: &String) -> String {
-    let disp_val = get_display_value(modal, attr_val);
-
-    match disp_val {
-        Value::String(val) => {
-            return val;
-        }
-        Value::Bool(val) => {
-            return val.to_string();
-        }
-        Value::Number(val) => {
-            return val.to_string();
-        }
-        _ => {
-            return String::new();
-        }
-    }
+/// - Same as `process_string`, but returns a `TemplateError` with the line and column of any problem in the template.
+#[allow(dead_code)]
+pub fn try_process_string<T: serde::ser::Serialize>(
+    html: &str,
+    modal: &T,
+) -> Result<String, TemplateError> {
+    Ok(Template::compile(html)?.render(modal))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use serde_json::{Value, json};
 
-    // get_display_value test
-    #[test]
-    fn test_get_display_value_simple() {
-        let modal = json!({"name": "Test"});
-        let result = get_display_value(&modal, &"name".to_string());
-        assert_eq!(result, json!("Test"));
+    fn parse(html: &str, modal: &Value) -> String {
+        Template::compile(html).unwrap().render_value(modal)
     }
 
-    #[test]
-    fn test_get_display_value_nested() {
-        let modal = json!({"user": {"name": "Alice"}});
-        let result = get_display_value(&modal, &"user.name".to_string());
-        assert_eq!(result, json!("Alice"));
-    }
-
-    #[test]
-    fn test_get_display_value_indexed() {
-        let modal = json!({"users": ["Alice", "Ben", "Rob"]});
-        let result = get_display_value(&modal, &"users[1]".to_string());
-        assert_eq!(result, json!("Ben"));
-    }
-
-    #[test]
-    fn test_get_display_value_empty() {
-        let modal: Value = serde_json::to_value(&"Alice").unwrap_or_default();
-        let result = get_display_value(&modal, &"".to_string());
-        assert_eq!(result, json!("Alice"));
-    }
-
-    #[test]
-    fn test_get_display_value_empty_indexed() {
-        let modal: Value = serde_json::to_value(&vec!["Alice", "Ben", "Rob"]).unwrap_or_default();
-        let result = get_display_value(&modal, &"[2]".to_string());
-        assert_eq!(result, json!("Rob"));
-    }
-
-    // get_display_string tests
-    #[test]
-    fn test_get_display_string_string() {
-        let modal = json!({"name": "Test"});
-        let result = get_display_string(&modal, &"name".to_string());
-        assert_eq!(result, "Test");
-    }
-
-    #[test]
-    fn test_get_display_string_bool() {
-        let modal = json!({"name": true});
-        let result = get_display_string(&modal, &"name".to_string());
-        assert_eq!(result, "true");
-    }
-
-    #[test]
-    fn test_get_display_string_int() {
-        let modal = json!({"name": 3});
-        let result = get_display_string(&modal, &"name".to_string());
-        assert_eq!(result, "3");
-    }
-
-    #[test]
-    fn test_get_display_string_float() {
-        let modal = json!({"name": 3.14});
-        let result = get_display_string(&modal, &"name".to_string());
-        assert_eq!(result, "3.14");
-    }
-
-    #[test]
-    fn test_get_display_string_invalid() {
-        let modal = json!({"name": []});
-        let result = get_display_string(&modal, &"name".to_string());
-        assert_eq!(result, String::new());
-    }
-
-    // parse_value tests
+    // value tests
     #[test]
     fn test_parse_value() {
         // Setup modal with a collection
@@ -609,14 +222,10 @@ mod tests {
             "user": "Bob"
         });
 
-        let mut ret_vec: Vec<u8> = vec![];
-
-        parse_value(&modal, &mut ret_vec, &String::from("user"));
-
-        assert_eq!(String::from_utf8(ret_vec).unwrap_or_default(), "Bob");
+        assert_eq!(parse("@value:user;", &modal), "Bob");
     }
 
-    // parse_for tests
+    // for tests
     #[test]
     fn test_parse_for() {
         // Setup modal with a collection
@@ -627,57 +236,41 @@ mod tests {
                 { "name": "Carol" }
             ]
         });
-        let mut foreach_modal: Vec<Option<Value>> = vec![];
-
-        let html_str = String::from("@for:users;{Name: @forvalue:0.name;<br/>}");
-        let html = html_str.as_bytes();
-        let mut i: usize = 0;
-
-        let mut ret_vec: Vec<u8> = vec![];
-
-        parse_for(
-            &modal,
-            &mut foreach_modal,
-            &mut ret_vec,
-            html,
-            html.len(),
-            &mut i,
-            &String::from("users"),
-        );
 
         assert_eq!(
-            String::from_utf8(ret_vec).unwrap_or_default(),
+            parse("@for:users;{Name: @forvalue:0.name;<br/>}", &modal),
             "Name: Alice<br/>Name: Bob<br/>Name: Carol<br/>"
         );
     }
 
-    // parse_if tests
+    #[test]
+    fn test_parse_forfor() {
+        let modal = json!({
+            "groups": [
+                { "users": [{ "name": "Alice" }, { "name": "Bob" }] },
+                { "users": [{ "name": "Carol" }] }
+            ]
+        });
+
+        assert_eq!(
+            parse(
+                "@for:groups;{[@forfor:0.users;{@forvalue:1.name;,}]}",
+                &modal
+            ),
+            "[Alice,Bob,][Carol,]"
+        );
+    }
+
+    // if tests
     #[test]
     fn test_parse_if_true() {
         // Setup modal with a collection
         let modal = json!({
             "bool": true
         });
-        let mut foreach_modal: Vec<Option<Value>> = vec![];
-
-        let html_str = String::from("@if:bool;{I am displaying!}");
-        let html = html_str.as_bytes();
-        let mut i: usize = 0;
-
-        let mut ret_vec: Vec<u8> = vec![];
-
-        parse_if(
-            &modal,
-            &mut foreach_modal,
-            &mut ret_vec,
-            html,
-            html.len(),
-            &mut i,
-            &String::from("bool"),
-        );
 
         assert_eq!(
-            String::from_utf8(ret_vec).unwrap_or_default(),
+            parse("@if:bool;{I am displaying!}", &modal),
             "I am displaying!"
         );
     }
@@ -688,25 +281,23 @@ mod tests {
         let modal = json!({
             "bool": false
         });
-        let mut foreach_modal: Vec<Option<Value>> = vec![];
-
-        let html_str = String::from("@if:bool;{I am not displaying!}");
-        let html = html_str.as_bytes();
-        let mut i: usize = 0;
-
-        let mut ret_vec: Vec<u8> = vec![];
-
-        parse_if(
-            &modal,
-            &mut foreach_modal,
-            &mut ret_vec,
-            html,
-            html.len(),
-            &mut i,
-            &String::from("bool"),
-        );
 
-        assert_eq!(String::from_utf8(ret_vec).unwrap_or_default(), "");
+        assert_eq!(parse("@if:bool;{I am not displaying!}", &modal), "");
+    }
+
+    #[test]
+    fn test_parse_forif() {
+        let modal = json!({
+            "users": [
+                { "name": "Alice", "admin": true },
+                { "name": "Bob", "admin": false }
+            ]
+        });
+
+        assert_eq!(
+            parse("@for:users;{@forif:0.admin;{@forvalue:0.name;}}", &modal),
+            "Alice"
+        );
     }
 
     // parse tests
@@ -717,13 +308,10 @@ mod tests {
             "user": "Bob"
         });
 
-        let html = String::from("Name: \\@value:user;<br/>");
-
-        let mut foreach_modal: Vec<Option<Value>> = vec![];
-
-        let result = parse(&html, &modal, &mut foreach_modal);
-
-        assert_eq!(result, "Name: @value:user;<br/>");
+        assert_eq!(
+            parse("Name: \\@value:user;<br/>", &modal),
+            "Name: @value:user;<br/>"
+        );
     }
 
     #[test]
@@ -733,13 +321,7 @@ mod tests {
             "user": "Bob"
         });
 
-        let html = String::from("Name: @value:user;<br/>");
-
-        let mut foreach_modal: Vec<Option<Value>> = vec![];
-
-        let result = parse(&html, &modal, &mut foreach_modal);
-
-        assert_eq!(result, "Name: Bob<br/>");
+        assert_eq!(parse("Name: @value:user;<br/>", &modal), "Name: Bob<br/>");
     }
 
     #[test]
@@ -753,12 +335,59 @@ mod tests {
             ]
         });
 
-        let html = String::from("@for:users;{Name: @forvalue:0.name;<br/>}");
+        assert_eq!(
+            parse("@for:users;{Name: @forvalue:0.name;<br/>}", &modal),
+            "Name: Alice<br/>Name: Bob<br/>Name: Carol<br/>"
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_try_process_string_error() {
+        let result = try_process_string("<p>@if:user;{</p>", &json!({"user": true}));
+
+        assert!(result.is_err());
+        assert_eq!(
+            process_string("<p>@if:user;{</p>", &json!({"user": true})),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_parse_value_escaped() {
+        let modal = json!({
+            "user": "<script>alert('x')</script>"
+        });
+
+        assert_eq!(
+            parse("Name: @value:user;", &modal),
+            "Name: &lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_token() {
+        let modal = json!({
+            "footer": "<em>Footer</em>"
+        });
+
+        assert_eq!(parse("@raw:footer;", &modal), "<em>Footer</em>");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_process_string() {
+        #[derive(serde::Serialize)]
+        struct User {
+            name: String,
+        }
 
-        let mut foreach_modal: Vec<Option<Value>> = vec![];
+        let user = User {
+            name: String::from("Test Name"),
+        };
 
-        let result = parse(&html, &modal, &mut foreach_modal);
+        let result = process_string(&String::from("Hello @value:name;!"), &user);
 
-        assert_eq!(result, "Name: Alice<br/>Name: Bob<br/>Name: Carol<br/>");
+        assert_eq!(result, "Hello Test Name!");
     }
 }
@@ -0,0 +1,430 @@
+use serde_json::Value;
+use std::fmt;
+
+/// Where a path starts resolving from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Root {
+    /// The modal passed to render.
+    Model,
+    /// The current item of the for loop at the given loop level.
+    Loop(usize),
+}
+
+/// A single step of a path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Key(String),
+    /// An index into a collection. A negative index counts back from the end, so `[-1]` is the last value.
+    Index(i64),
+    /// Written as `[start..end]`, the values from `start` up to, but not including, `end`. Either bound may be
+    /// left out, and negative bounds count back from the end. Strings are sliced by character.
+    Slice(Option<i64>, Option<i64>),
+    /// Written as `?.`. A null or missing value before it makes the whole path null, rather than missing.
+    Optional,
+}
+
+/// What an optional path resolves to when a value before its `?.` is null or missing.
+static NULL: Value = Value::Null;
+
+/// A pre-parsed value key such as `user_vec[0].str_vec[2]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub root: Root,
+    pub segments: Vec<Segment>,
+}
+
+impl Path {
+    /// Parses a key that resolves from the modal.
+    pub fn parse(key: &str) -> Result<Path, String> {
+        Ok(Path {
+            root: Root::Model,
+            segments: parse_segments(key)?,
+        })
+    }
+
+    /// Parses a key whose first element is a loop level, such as `0.name`.
+    pub fn parse_loop(key: &str) -> Result<Path, String> {
+        let mut parts = key.splitn(2, '.');
+        let idx_str = parts.next().unwrap_or_default();
+        let level = idx_str
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid loop level `{}`!", idx_str))?;
+
+        Ok(Path {
+            root: Root::Loop(level),
+            segments: parse_segments(parts.next().unwrap_or_default())?,
+        })
+    }
+
+    /// Walks the path's segments starting at the supplied value.
+    pub fn resolve<'a>(&self, start: &'a Value) -> Option<&'a Value> {
+        resolve_segments(start, &self.segments)
+    }
+
+    /// Where the first slice of the path is, if it has one. Slices build new values, so a path with a slice
+    /// cannot be resolved to a reference into the modal.
+    pub fn slice_start(&self) -> Option<usize> {
+        self.segments
+            .iter()
+            .position(|segment| matches!(segment, Segment::Slice(..)))
+    }
+
+    /// The first key of the path, which may name a loop variable rather than a field of the modal.
+    pub fn first_key(&self) -> Option<&str> {
+        match self.segments.first() {
+            Some(Segment::Key(key)) => Some(key),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Root::Loop(level) = self.root {
+            write!(f, "{}", level)?;
+        }
+
+        for (idx, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if idx == 0 && self.root == Root::Model => write!(f, "{}", key)?,
+                Segment::Key(key) => write!(f, ".{}", key)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+                Segment::Slice(start, end) => {
+                    let bound = |bound: &Option<i64>| bound.map(|bound| bound.to_string());
+                    write!(
+                        f,
+                        "[{}..{}]",
+                        bound(start).unwrap_or_default(),
+                        bound(end).unwrap_or_default()
+                    )?;
+                }
+                Segment::Optional => write!(f, "?")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Walks the supplied segments starting at the supplied value.
+pub fn resolve_segments<'a>(start: &'a Value, segments: &[Segment]) -> Option<&'a Value> {
+    let mut disp_val = start;
+
+    for (idx, segment) in segments.iter().enumerate() {
+        let next = match segment {
+            Segment::Key(key) => disp_val.get(key),
+            Segment::Index(index) => get_index(disp_val, *index),
+            Segment::Slice(..) => None,
+            Segment::Optional if disp_val.is_null() => return Some(&NULL),
+            Segment::Optional => Some(disp_val),
+        };
+
+        disp_val = match next {
+            Some(next) => next,
+            None if segments.get(idx + 1) == Some(&Segment::Optional) => return Some(&NULL),
+            None => return None,
+        };
+    }
+
+    Some(disp_val)
+}
+
+/// Walks segments that may include slices. The values after a slice are copies rather than references into
+/// the start value.
+pub fn resolve_sliced(start: &Value, segments: &[Segment]) -> Option<Value> {
+    let Some(slice_at) = segments
+        .iter()
+        .position(|segment| matches!(segment, Segment::Slice(..)))
+    else {
+        return resolve_segments(start, segments).cloned();
+    };
+    let Segment::Slice(from, to) = segments[slice_at] else {
+        return None;
+    };
+
+    let sliced = slice(resolve_segments(start, &segments[..slice_at])?, from, to)?;
+    resolve_sliced(&sliced, &segments[slice_at + 1..])
+}
+
+fn get_index(val: &Value, index: i64) -> Option<&Value> {
+    let arr = val.as_array()?;
+    let index = if index < 0 {
+        arr.len().checked_sub(index.unsigned_abs() as usize)?
+    } else {
+        index as usize
+    };
+
+    arr.get(index)
+}
+
+/// Slices a collection or a string. Bounds past either end are clamped, like Python's slices.
+fn slice(val: &Value, from: Option<i64>, to: Option<i64>) -> Option<Value> {
+    let clamp = |bound: Option<i64>, len: usize, default: usize| match bound {
+        None => default,
+        Some(bound) if bound < 0 => len.saturating_sub(bound.unsigned_abs() as usize),
+        Some(bound) => (bound as usize).min(len),
+    };
+
+    match val {
+        Value::Array(arr) => {
+            let start = clamp(from, arr.len(), 0);
+            let end = clamp(to, arr.len(), arr.len()).max(start);
+
+            Some(Value::Array(arr[start..end].to_vec()))
+        }
+        Value::String(text) => {
+            let len = text.chars().count();
+            let start = clamp(from, len, 0);
+            let end = clamp(to, len, len).max(start);
+
+            Some(Value::String(
+                text.chars().skip(start).take(end - start).collect(),
+            ))
+        }
+        Value::Null => Some(Value::Null),
+        _ => None,
+    }
+}
+
+fn parse_segments(key: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = vec![];
+    let bytes = key.as_bytes();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'.' => pos += 1,
+            b'?' => {
+                segments.push(Segment::Optional);
+                pos += 1;
+            }
+            b'[' => {
+                let Some(len) = key[pos..].find(']') else {
+                    return Err(format!("Invalid index `{}` in `{}`!", &key[pos..], key));
+                };
+
+                segments.push(parse_index(&key[pos + 1..pos + len]).ok_or_else(|| {
+                    format!("Invalid index `{}` in `{}`!", &key[pos..=pos + len], key)
+                })?);
+                pos += len + 1;
+            }
+            _ => {
+                let start = pos;
+
+                while pos < bytes.len() && !matches!(bytes[pos], b'.' | b'?' | b'[') {
+                    pos += 1;
+                }
+
+                segments.push(Segment::Key(key[start..pos].to_string()));
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Parses the inside of `[...]`, either an index such as `-1` or a slice such as `0..5`.
+fn parse_index(index: &str) -> Option<Segment> {
+    let bound = |bound: &str| match bound.trim() {
+        "" => Some(None),
+        bound => bound.parse::<i64>().ok().map(Some),
+    };
+
+    match index.split_once("..") {
+        Some((from, to)) => Some(Segment::Slice(bound(from)?, bound(to)?)),
+        None => index.trim().parse::<i64>().ok().map(Segment::Index),
+    }
+}
+
+/// Formats a scalar value for output. Collections and null display as empty.
+pub fn display_string(disp_val: &Value) -> String {
+    match disp_val {
+        Value::String(val) => val.clone(),
+        Value::Bool(val) => val.to_string(),
+        Value::Number(val) => val.to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn get_display_value(modal: &Value, attr_val: &str) -> Value {
+        Path::parse(attr_val)
+            .ok()
+            .and_then(|path| resolve_sliced(modal, &path.segments))
+            .unwrap_or_default()
+    }
+
+    fn get_display_string(modal: &Value, attr_val: &str) -> String {
+        display_string(&get_display_value(modal, attr_val))
+    }
+
+    // get_display_value test
+    #[test]
+    fn test_get_display_value_simple() {
+        let modal = json!({"name": "Test"});
+        let result = get_display_value(&modal, "name");
+        assert_eq!(result, json!("Test"));
+    }
+
+    #[test]
+    fn test_get_display_value_nested() {
+        let modal = json!({"user": {"name": "Alice"}});
+        let result = get_display_value(&modal, "user.name");
+        assert_eq!(result, json!("Alice"));
+    }
+
+    #[test]
+    fn test_get_display_value_indexed() {
+        let modal = json!({"users": ["Alice", "Ben", "Rob"]});
+        let result = get_display_value(&modal, "users[1]");
+        assert_eq!(result, json!("Ben"));
+    }
+
+    #[test]
+    fn test_get_display_value_empty() {
+        let modal: Value = serde_json::to_value("Alice").unwrap_or_default();
+        let result = get_display_value(&modal, "");
+        assert_eq!(result, json!("Alice"));
+    }
+
+    #[test]
+    fn test_get_display_value_empty_indexed() {
+        let modal: Value = serde_json::to_value(vec!["Alice", "Ben", "Rob"]).unwrap_or_default();
+        let result = get_display_value(&modal, "[2]");
+        assert_eq!(result, json!("Rob"));
+    }
+
+    #[test]
+    fn test_get_display_value_nested_indexed() {
+        let modal = json!({"vec_vec": [[0, 1], [2, 3]]});
+        let result = get_display_value(&modal, "vec_vec[1][0]");
+        assert_eq!(result, json!(2));
+    }
+
+    #[test]
+    fn test_path_parse_loop() {
+        let path = Path::parse_loop("1.users[0]").unwrap();
+        assert_eq!(path.root, Root::Loop(1));
+        assert_eq!(
+            path.segments,
+            vec![Segment::Key(String::from("users")), Segment::Index(0)]
+        );
+    }
+
+    #[test]
+    fn test_get_display_value_optional() {
+        let modal = json!({"user": {"address": null, "tags": ["a"]}});
+        let path = Path::parse("user.address?.city").unwrap();
+
+        assert_eq!(path.resolve(&modal), Some(&Value::Null));
+        assert_eq!(
+            Path::parse("user.phone?.number").unwrap().resolve(&modal),
+            Some(&Value::Null)
+        );
+        assert_eq!(
+            Path::parse("user.tags[0]?.x").unwrap().resolve(&modal),
+            None
+        );
+        assert_eq!(
+            Path::parse("user.address.city").unwrap().resolve(&modal),
+            None
+        );
+        assert_eq!(path.to_string(), "user.address?.city");
+    }
+
+    #[test]
+    fn test_get_display_value_negative_index() {
+        let modal = json!({"users": ["Alice", "Ben", "Rob"]});
+
+        assert_eq!(get_display_value(&modal, "users[-1]"), json!("Rob"));
+        assert_eq!(get_display_value(&modal, "users[-3]"), json!("Alice"));
+        assert_eq!(get_display_value(&modal, "users[-4]"), json!(null));
+        assert_eq!(get_display_value(&modal, "users[9]"), json!(null));
+    }
+
+    #[test]
+    fn test_resolve_sliced() {
+        let modal =
+            json!({"users": [{"name": "a"}, {"name": "b"}, {"name": "c"}], "title": "héllo"});
+        let resolve = |key: &str| resolve_sliced(&modal, &Path::parse(key).unwrap().segments);
+
+        assert_eq!(
+            resolve("users[0..2]"),
+            Some(json!([{"name": "a"}, {"name": "b"}]))
+        );
+        assert_eq!(resolve("users[1..][0].name"), Some(json!("b")));
+        assert_eq!(
+            resolve("users[-2..]"),
+            Some(json!([{"name": "b"}, {"name": "c"}]))
+        );
+        assert_eq!(resolve("users[..10]").unwrap().as_array().unwrap().len(), 3);
+        assert_eq!(resolve("users[2..1]"), Some(json!([])));
+        assert_eq!(resolve("title[1..3]"), Some(json!("él")));
+        assert_eq!(resolve("users[0..1].name"), None);
+        assert_eq!(resolve("missing[0..1]"), None);
+    }
+
+    #[test]
+    fn test_path_display() {
+        assert_eq!(
+            Path::parse("users[0].name").unwrap().to_string(),
+            "users[0].name"
+        );
+        assert_eq!(Path::parse("[2]").unwrap().to_string(), "[2]");
+        assert_eq!(
+            Path::parse("users[-1].tags[1..]").unwrap().to_string(),
+            "users[-1].tags[1..]"
+        );
+        assert_eq!(
+            Path::parse_loop("1.tags[3]").unwrap().to_string(),
+            "1.tags[3]"
+        );
+    }
+
+    #[test]
+    fn test_path_parse_bad_index() {
+        assert!(Path::parse("users[one]").is_err());
+        assert!(Path::parse("users[1").is_err());
+        assert!(Path::parse("users[1..x]").is_err());
+    }
+
+    // get_display_string tests
+    #[test]
+    fn test_get_display_string_string() {
+        let modal = json!({"name": "Test"});
+        let result = get_display_string(&modal, "name");
+        assert_eq!(result, "Test");
+    }
+
+    #[test]
+    fn test_get_display_string_bool() {
+        let modal = json!({"name": true});
+        let result = get_display_string(&modal, "name");
+        assert_eq!(result, "true");
+    }
+
+    #[test]
+    fn test_get_display_string_int() {
+        let modal = json!({"name": 3});
+        let result = get_display_string(&modal, "name");
+        assert_eq!(result, "3");
+    }
+
+    #[test]
+    fn test_get_display_string_float() {
+        let modal = json!({"name": 2.5});
+        let result = get_display_string(&modal, "name");
+        assert_eq!(result, "2.5");
+    }
+
+    #[test]
+    fn test_get_display_string_invalid() {
+        let modal = json!({"name": []});
+        let result = get_display_string(&modal, "name");
+        assert_eq!(result, String::new());
+    }
+}
@@ -0,0 +1,360 @@
+use super::engine::{Engine, Undefined};
+use super::error::RenderError;
+use super::escape::Escape;
+use super::expr::{Expr, Scope};
+use super::path::{Path, Root, resolve_segments};
+use super::template::{LoopBinding, Macro, Node, Template};
+use super::tokens::TokenContext;
+use serde_json::{Value, json};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// The name that resolves to the metadata of the innermost loop.
+const LOOP_META_NAME: &str = "loop";
+/// How deeply macro calls may nest, so a macro that always calls itself cannot overflow the stack.
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// The current item of a for loop, along with the variables it is bound to and its position in the loop. The
+/// parameters of a macro call are frames too, each binding a single value with no position.
+pub struct Frame<'a> {
+    pub binding: Option<Binding<'a>>,
+    /// The object key or array index of the value.
+    pub key: Value,
+    pub value: Value,
+    /// The index of the item and the length of its loop, from which `loop` is built when a path names it.
+    pub position: Option<(usize, usize)>,
+}
+
+/// The variables a frame binds, borrowed from the template.
+#[derive(Clone, Copy)]
+pub enum Binding<'a> {
+    Loop(&'a LoopBinding),
+    /// A macro parameter, bound to the frame's value.
+    Param(&'a str),
+}
+
+impl Frame<'_> {
+    /// The value of the named loop variable, if this frame binds it.
+    fn var(&self, name: &str) -> Option<&Value> {
+        match self.binding? {
+            Binding::Loop(LoopBinding::Value(value_name)) if value_name == name => {
+                Some(&self.value)
+            }
+            Binding::Loop(LoopBinding::Pair(key_name, _)) if key_name == name => Some(&self.key),
+            Binding::Loop(LoopBinding::Pair(_, value_name)) if value_name == name => {
+                Some(&self.value)
+            }
+            Binding::Param(param) if param == name => Some(&self.value),
+            _ => None,
+        }
+    }
+}
+
+/// Walks a compiled node tree, writing the output for a single modal.
+pub struct Renderer<'a> {
+    engine: &'a Engine,
+    macros: &'a HashMap<String, Macro>,
+    modal: &'a Value,
+    foreach_modal: Vec<Frame<'a>>,
+    macro_depth: usize,
+    out: String,
+    /// The first error found while evaluating an expression.
+    error: Option<RenderError>,
+    /// The first path that did not resolve while evaluating the current expression.
+    missing: RefCell<Option<String>>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(engine: &'a Engine, template: &'a Template, modal: &'a Value) -> Renderer<'a> {
+        Renderer {
+            engine,
+            macros: template.macros(),
+            modal,
+            foreach_modal: vec![],
+            macro_depth: 0,
+            out: String::new(),
+            error: None,
+            missing: RefCell::new(None),
+        }
+    }
+
+    /// The modal being rendered.
+    pub fn modal(&self) -> &Value {
+        self.modal
+    }
+
+    /// The items of the for loops currently being rendered, outermost first.
+    pub fn loop_stack(&self) -> &[Frame<'a>] {
+        &self.foreach_modal
+    }
+
+    /// Resolves a path against the modal and the current loop variables.
+    pub fn lookup_path(&self, path: &Path) -> Option<Cow<'_, Value>> {
+        lookup(self.modal, &self.foreach_modal, path)
+    }
+
+    pub fn write_value(&mut self, escape: Escape, val: &Value) {
+        escape.write(val, &mut self.out);
+    }
+
+    /// The rendered output. Values that failed to evaluate are displayed as empty.
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    /// The rendered output, or the first error found while evaluating an expression.
+    pub fn into_result(self) -> Result<String, RenderError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.out),
+        }
+    }
+
+    pub fn render_nodes(&mut self, nodes: &'a [Node]) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => {
+                    self.out.push_str(text);
+                }
+                Node::Value { expr, escape } => match self.eval_defined(expr) {
+                    Ok(val) => escape.write(&val, &mut self.out),
+                    Err(path) if self.engine.undefined() == Undefined::Debug => {
+                        escape.write(
+                            &Value::from(format!("[undefined: {}]", path)),
+                            &mut self.out,
+                        );
+                    }
+                    Err(_) => {}
+                },
+                Node::If {
+                    cond,
+                    body,
+                    else_body,
+                } => {
+                    let val = self.eval(cond);
+
+                    if self.is_true(&val) {
+                        self.render_nodes(body);
+                    } else {
+                        self.render_nodes(else_body);
+                    }
+                }
+                Node::For {
+                    binding,
+                    source,
+                    body,
+                    empty_body,
+                } => {
+                    // objects iterate in key order, since serde_json keeps its maps sorted.
+                    let items: Vec<(Value, Value)> = match self.eval(source) {
+                        Value::Array(arr) => arr
+                            .into_iter()
+                            .enumerate()
+                            .map(|(index, val)| (Value::from(index), val))
+                            .collect(),
+                        Value::Object(map) => map
+                            .into_iter()
+                            .map(|(key, val)| (Value::String(key), val))
+                            .collect(),
+                        _ => vec![],
+                    };
+
+                    if items.is_empty() {
+                        self.render_nodes(empty_body);
+                        continue;
+                    }
+
+                    let length = items.len();
+
+                    for (index, (key, val)) in items.into_iter().enumerate() {
+                        self.foreach_modal.push(Frame {
+                            binding: binding.as_ref().map(Binding::Loop),
+                            key,
+                            value: val,
+                            position: Some((index, length)),
+                        });
+                        self.render_nodes(body);
+                        self.foreach_modal.pop();
+                    }
+                }
+                Node::Include { nodes, .. } | Node::Block { body: nodes, .. } => {
+                    self.render_nodes(nodes);
+                }
+                Node::Call { name, args } => {
+                    let args: Vec<Value> = args.iter().map(|arg| self.eval(arg)).collect();
+                    self.call_macro(name, args);
+                }
+                Node::Parent | Node::Macro { .. } | Node::Extends { .. } => {}
+                Node::Custom {
+                    name,
+                    key,
+                    escape,
+                    body,
+                } => {
+                    let engine = self.engine;
+                    let result = match engine.custom_token(name) {
+                        Some(token) => token
+                            .handler
+                            .render(&mut TokenContext::new(self, key, *escape, body)),
+                        None => Err(String::from("token is not registered")),
+                    };
+
+                    if let Err(message) = result {
+                        self.error.get_or_insert(RenderError::Token {
+                            name: name.clone(),
+                            message,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Renderer<'_> {
+    /// Evaluates an expression, keeping the first error and evaluating to null instead.
+    fn eval(&mut self, expr: &Expr) -> Value {
+        self.eval_defined(expr).unwrap_or_default()
+    }
+
+    /// Evaluates an expression, returning the missing path instead when the value is null because a path did
+    /// not resolve. With `Undefined::Strict`, any path that did not resolve is kept as the error, whatever the
+    /// value, unless it was guarded by `??`, `?.` or `default`.
+    fn eval_defined(&mut self, expr: &Expr) -> Result<Value, String> {
+        self.missing.borrow_mut().take();
+
+        let val = match expr.eval(&*self) {
+            Ok(val) => val,
+            Err(error) => {
+                self.error.get_or_insert(error);
+                return Ok(Value::Null);
+            }
+        };
+
+        match self.missing.borrow_mut().take() {
+            Some(path) if self.engine.undefined() == Undefined::Strict => {
+                self.error
+                    .get_or_insert(RenderError::Undefined { path: path.clone() });
+
+                Err(path)
+            }
+            Some(path) if val.is_null() => Err(path),
+            _ => Ok(val),
+        }
+    }
+
+    /// Displays a macro with only its parameters in scope, so it renders the same wherever it is called from.
+    fn call_macro(&mut self, name: &str, args: Vec<Value>) {
+        let macros = self.macros;
+
+        let Some(def) = macros.get(name) else {
+            self.macro_error(name, "macro is not defined");
+            return;
+        };
+
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            self.macro_error(name, "macro calls are nested too deeply");
+            return;
+        }
+
+        let mut args = args.into_iter();
+        let params = def
+            .params
+            .iter()
+            .map(|param| Frame {
+                binding: Some(Binding::Param(param)),
+                key: Value::Null,
+                value: args.next().unwrap_or_default(),
+                position: None,
+            })
+            .collect();
+
+        let outer = std::mem::replace(&mut self.foreach_modal, params);
+        self.macro_depth += 1;
+        self.render_nodes(&def.body);
+        self.macro_depth -= 1;
+        self.foreach_modal = outer;
+    }
+
+    fn macro_error(&mut self, name: &str, message: &str) {
+        self.error.get_or_insert(RenderError::Macro {
+            name: name.to_string(),
+            message: message.to_string(),
+        });
+    }
+}
+
+impl Scope for Renderer<'_> {
+    fn lookup(&self, path: &Path) -> Option<Cow<'_, Value>> {
+        lookup(self.modal, &self.foreach_modal, path)
+    }
+
+    fn undefined(&self, path: &Path) {
+        self.missing
+            .borrow_mut()
+            .get_or_insert_with(|| path.to_string());
+    }
+
+    fn is_true(&self, val: &Value) -> bool {
+        self.engine.truthiness().is_true(val)
+    }
+
+    fn filter(&self, name: &str, val: &Value, args: &[Value]) -> Result<Value, RenderError> {
+        self.engine.apply_filter(name, val, args)
+    }
+
+    fn call(&self, name: &str, args: &[Value]) -> Result<Value, RenderError> {
+        self.engine.call_function(name, args)
+    }
+}
+
+/// Resolves a path. A path whose first key names a loop variable resolves from the innermost loop with that
+/// name, `loop` resolves from the innermost loop's metadata, and any other path resolves from the modal.
+fn lookup<'a>(modal: &'a Value, foreach_modal: &'a [Frame], path: &Path) -> Option<Cow<'a, Value>> {
+    match path.root {
+        Root::Loop(level) => path
+            .resolve(&foreach_modal.get(level)?.value)
+            .map(Cow::Borrowed),
+        Root::Model => {
+            if let Some(key) = path.first_key() {
+                if let Some(var) = foreach_modal.iter().rev().find_map(|frame| frame.var(key)) {
+                    return resolve_segments(var, &path.segments[1..]).map(Cow::Borrowed);
+                }
+
+                if key == LOOP_META_NAME
+                    && foreach_modal.iter().any(|frame| frame.position.is_some())
+                {
+                    let meta = loop_meta(foreach_modal);
+                    return resolve_segments(&meta, &path.segments[1..])
+                        .cloned()
+                        .map(Cow::Owned);
+                }
+            }
+
+            path.resolve(modal).map(Cow::Borrowed)
+        }
+    }
+}
+
+/// Builds the `loop` object for the innermost for loop, with the enclosing loop's object as its parent. Frames
+/// of macro parameters are skipped, and the object is null outside of any loop.
+fn loop_meta(foreach_modal: &[Frame]) -> Value {
+    let Some((at, (index, length))) = foreach_modal
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(at, frame)| Some((at, frame.position?)))
+    else {
+        return Value::Null;
+    };
+
+    json!({
+        "index": index + 1,
+        "index0": index,
+        "first": index == 0,
+        "last": index + 1 == length,
+        "length": length,
+        "parent": loop_meta(&foreach_modal[..at]),
+    })
+}
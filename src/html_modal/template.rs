@@ -0,0 +1,2286 @@
+use super::context::HtmlContext;
+use super::engine::{BlockSyntax, Engine};
+use super::error::{TemplateError, TemplateErrorKind};
+use super::escape::Escape;
+use super::expr::Expr;
+use super::path::Path;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const MAX_TOKEN_LEN: usize = 1000;
+const INLINE_NAME: &str = "<inline>";
+/// Tokens without a key, which may only follow the block they belong to.
+const KEYLESS_TOKENS: [&str; 7] = [
+    "else",
+    "empty",
+    "endif",
+    "endfor",
+    "endblock",
+    "endmacro",
+    "endverbatim",
+];
+const COMMENT_START: &[u8] = b"@*";
+const COMMENT_END: &[u8] = b"*@";
+const VERBATIM_START: &[u8] = b"@verbatim;";
+const VERBATIM_END: &[u8] = b"@endverbatim;";
+/// Chooses the block syntax of a template when it is the first token of the template.
+const SYNTAX_PRAGMA: &str = "@syntax:";
+/// The end tags of the built in block tokens.
+const END_TAGS: [&str; 4] = ["endif", "endfor", "endblock", "endmacro"];
+/// The tokens that end the body of an if when using end tags.
+const IF_ENDS: [&str; 4] = ["elseif", "forelseif", "else", "endif"];
+/// The tokens that end the body of a for when using end tags.
+const FOR_ENDS: [&str; 2] = ["empty", "endfor"];
+
+/// A node of a compiled template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Raw text, copied to the output as is.
+    Text(String),
+    /// Displays the value of the expression, escaped for the output.
+    Value { expr: Expr, escape: Escape },
+    /// Displays the body if the condition is true, otherwise displays the else body. An `@elseif` is an If
+    /// nested as the only node of the else body.
+    If {
+        cond: Expr,
+        body: Vec<Node>,
+        else_body: Vec<Node>,
+    },
+    /// Repeats the body for each value in the source collection, binding each value to the loop variables if the
+    /// loop names them. Displays the empty body instead if there is nothing to loop over.
+    For {
+        binding: Option<LoopBinding>,
+        source: Expr,
+        body: Vec<Node>,
+        empty_body: Vec<Node>,
+    },
+    /// The nodes of an included template, displayed with the current modal and loop variables.
+    Include { name: String, nodes: Vec<Node> },
+    /// A named section of a layout, which templates extending the layout can replace.
+    Block { name: String, body: Vec<Node> },
+    /// Stands in for the layout's content of the block it is in. Replaced when the template is compiled.
+    Parent,
+    /// Marks where a macro was defined. Displays nothing, the macro itself is kept by the template.
+    Macro { name: String },
+    /// Displays the named macro, with its parameters bound to the values of the arguments.
+    Call { name: String, args: Vec<Expr> },
+    /// Marks a template that extends a layout. Only found in the nodes of a template before the layout replaces
+    /// them.
+    Extends { name: String },
+    /// A token type registered on the engine. Inline tokens have no body.
+    Custom {
+        name: String,
+        key: String,
+        escape: Escape,
+        body: Vec<Node>,
+    },
+}
+
+impl Node {
+    /// The node lists nested inside this node.
+    fn children_mut(&mut self) -> Vec<&mut Vec<Node>> {
+        match self {
+            Node::If {
+                body, else_body, ..
+            } => vec![body, else_body],
+            Node::For {
+                body, empty_body, ..
+            } => vec![body, empty_body],
+            Node::Include { nodes, .. } => vec![nodes],
+            Node::Block { body, .. } | Node::Custom { body, .. } => vec![body],
+            Node::Text(_)
+            | Node::Value { .. }
+            | Node::Parent
+            | Node::Macro { .. }
+            | Node::Call { .. }
+            | Node::Extends { .. } => vec![],
+        }
+    }
+}
+
+/// Where the nodes being parsed end.
+#[derive(Clone, Copy)]
+enum BlockEnd<'e> {
+    /// The end of the template.
+    Eof,
+    /// The `}` closing the block token that started at the position.
+    Brace(usize),
+    /// Any of the named end tags, for the block token that started at the position.
+    Tags(usize, &'e [&'e str]),
+}
+
+/// A reusable piece of a template, defined with `@macro:name(params);{...}` and displayed with
+/// `@call:name(args);`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub params: Vec<String>,
+    pub body: Vec<Node>,
+}
+
+/// The loop variables named by a for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoopBinding {
+    /// `item in items`, binding each value.
+    Value(String),
+    /// `(key, value) in items`, binding each object key or array index along with its value.
+    Pair(String, String),
+}
+
+/// A template that has been parsed once into a node tree and can be rendered any number of times.
+///
+/// # Examples
+///
+/// ```
+/// let template = Template::compile("Hello @value:name;!")?;
+///
+/// let first = template.render(&user);
+/// let second = template.render(&other_user);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+    macros: HashMap<String, Macro>,
+    /// The engine that compiled the template, whose filters, functions and tokens it renders with.
+    engine: Engine,
+}
+
+#[allow(dead_code)]
+impl Template {
+    /// Parses the supplied template source into a reusable node tree. Only built in filters are allowed, see
+    /// `Engine::compile` for registered ones.
+    pub fn compile(source: &str) -> Result<Template, TemplateError> {
+        Template::compile_named(INLINE_NAME, source)
+    }
+
+    /// Parses the supplied template source, reporting errors against the supplied template name.
+    pub fn compile_named(name: &str, source: &str) -> Result<Template, TemplateError> {
+        Template::compile_named_with(&Engine::default(), name, source)
+    }
+
+    /// Parses the supplied template source, allowing the filters and functions registered on the engine.
+    pub fn compile_with(engine: &Engine, source: &str) -> Result<Template, TemplateError> {
+        Template::compile_named_with(engine, INLINE_NAME, source)
+    }
+
+    /// Parses the supplied template source with the engine's filters and functions, reporting errors against the
+    /// supplied template name.
+    pub fn compile_named_with(
+        engine: &Engine,
+        name: &str,
+        source: &str,
+    ) -> Result<Template, TemplateError> {
+        let mut parser = Parser::new(
+            engine,
+            name,
+            source,
+            HtmlContext::new(),
+            vec![name.to_string()],
+        );
+
+        Ok(Template {
+            nodes: parser.parse_template()?,
+            macros: parser.macros,
+            engine: engine.clone(),
+        })
+    }
+
+    /// Renders the template against the supplied modal, using the options of the engine that compiled it.
+    pub fn render<T: serde::ser::Serialize>(&self, modal: &T) -> String {
+        self.engine.render(self, modal)
+    }
+
+    /// Renders the template against a modal that has already been converted to a serde Value.
+    pub fn render_value(&self, modal: &Value) -> String {
+        self.engine.render_value(self, modal)
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The macros defined by the template, along with those of the templates it includes or extends.
+    pub fn macros(&self) -> &HashMap<String, Macro> {
+        &self.macros
+    }
+}
+
+struct Parser<'a> {
+    engine: &'a Engine,
+    name: &'a str,
+    source: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    context: HtmlContext,
+    /// The names of the templates being parsed, from the outermost down to this one.
+    includes: Vec<String>,
+    /// How many blocks deep the parser is.
+    depth: usize,
+    /// The names of the `@block`s found so far, and whether the parser is inside each one.
+    blocks: Vec<(String, bool)>,
+    /// The layout named by `@extends`, along with the position of the token.
+    extends: Option<(String, usize)>,
+    macros: HashMap<String, Macro>,
+    /// The name of the template that defines each macro, so a template included more than once can define
+    /// its macros again.
+    macro_sources: HashMap<String, String>,
+    syntax: BlockSyntax,
+    /// Whether the whitespace at the start of the following text is dropped, after a token ending in `;-`.
+    trim_next: bool,
+    /// The HTML context at the start and end of the body of each `@block` parsed, including those of included
+    /// templates and layouts.
+    block_contexts: HashMap<String, (HtmlContext, HtmlContext)>,
+    /// Where the body of each `@block` in this template's own source starts, so it can be parsed again.
+    block_bodies: HashMap<String, BlockBody>,
+    /// The contexts of the layout's blocks, while parsing a block that overrides one of them again. An
+    /// `@parent` moves the context from the start of the layout's block to its end.
+    layout_blocks: HashMap<String, (HtmlContext, HtmlContext)>,
+}
+
+/// Where the body of a `@block` starts in the template source.
+#[derive(Clone, Copy)]
+struct BlockBody {
+    token_start: usize,
+    body_start: usize,
+    trim_next: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(
+        engine: &'a Engine,
+        name: &'a str,
+        source: &'a str,
+        context: HtmlContext,
+        includes: Vec<String>,
+    ) -> Parser<'a> {
+        Parser {
+            engine,
+            name,
+            source,
+            bytes: source.as_bytes(),
+            pos: 0,
+            context,
+            includes,
+            depth: 0,
+            blocks: vec![],
+            extends: None,
+            macros: HashMap::new(),
+            macro_sources: HashMap::new(),
+            syntax: engine.block_syntax(),
+            trim_next: false,
+            block_contexts: HashMap::new(),
+            block_bodies: HashMap::new(),
+            layout_blocks: HashMap::new(),
+        }
+    }
+
+    fn error(&self, pos: usize, kind: TemplateErrorKind) -> TemplateError {
+        TemplateError::at(self.name, self.source, pos, kind)
+    }
+
+    /// Parses a whole template. A template that extends a layout is replaced by the layout, with the template's
+    /// blocks in place of the layout's blocks of the same name.
+    fn parse_template(&mut self) -> Result<Vec<Node>, TemplateError> {
+        self.parse_syntax()?;
+        let mut nodes = self.parse_nodes(BlockEnd::Eof)?;
+
+        let Some((layout, token_start)) = self.extends.take() else {
+            return Ok(nodes);
+        };
+
+        let mut blocks = HashMap::new();
+        collect_blocks(&mut nodes, &mut blocks);
+
+        let own_contexts = std::mem::take(&mut self.block_contexts);
+        let mut layout_nodes = self.parse_child(token_start, &layout, HtmlContext::new())?;
+        let layout_contexts = self.block_contexts.clone();
+
+        for (name, body) in blocks.iter_mut() {
+            let Some((start, end)) = layout_contexts.get(name) else {
+                continue;
+            };
+
+            // a block of an included template cannot be parsed again, so it must already match the layout.
+            let Some(block_body) = self.block_bodies.get(name).copied() else {
+                if own_contexts.get(name) != Some(&(start.clone(), end.clone())) {
+                    return Err(self.block_mismatch(token_start, name));
+                }
+
+                continue;
+            };
+
+            *body = self.reparse_block(name, block_body, &layout_contexts)?;
+        }
+
+        override_blocks(&mut layout_nodes, &blocks);
+
+        Ok(layout_nodes)
+    }
+
+    /// Parses the body of one of this template's blocks again, starting from the HTML context of the layout's
+    /// block it overrides, so its values are escaped for where they end up. The body must end in the same context
+    /// as the layout's block.
+    fn reparse_block(
+        &mut self,
+        name: &str,
+        block_body: BlockBody,
+        layout_contexts: &HashMap<String, (HtmlContext, HtmlContext)>,
+    ) -> Result<Vec<Node>, TemplateError> {
+        let (start, end) = &layout_contexts[name];
+        let mut parser = Parser::new(
+            self.engine,
+            self.name,
+            self.source,
+            start.clone(),
+            self.includes.clone(),
+        );
+        parser.pos = block_body.body_start;
+        parser.trim_next = block_body.trim_next;
+        parser.syntax = self.syntax;
+        parser.blocks = vec![(name.to_string(), true)];
+        parser.layout_blocks = layout_contexts.clone();
+
+        let body = parser.parse_closed_block(block_body.token_start, "endblock")?;
+
+        if parser.context != *end {
+            return Err(self.block_mismatch(block_body.token_start, name));
+        }
+
+        self.block_contexts.extend(parser.block_contexts);
+        Ok(body)
+    }
+
+    fn block_mismatch(&self, pos: usize, name: &str) -> TemplateError {
+        self.error(
+            pos,
+            TemplateErrorKind::ContextMismatch(format!(
+                "block `{}` does not end in the same HTML context as the layout's block",
+                name
+            )),
+        )
+    }
+
+    /// Reads an `@syntax:braces;` or `@syntax:endtags;` at the very start of the template, along with the line
+    /// break after it.
+    fn parse_syntax(&mut self) -> Result<(), TemplateError> {
+        if !self.source.starts_with(SYNTAX_PRAGMA) {
+            return Ok(());
+        }
+
+        self.pos = SYNTAX_PRAGMA.len();
+        let key_start = self.pos;
+        let token_key = self.parse_token_key(0)?;
+
+        self.syntax = match token_key.trim().to_lowercase().as_str() {
+            "braces" => BlockSyntax::Braces,
+            "endtags" => BlockSyntax::EndTags,
+            _ => {
+                return Err(self.error(
+                    key_start,
+                    TemplateErrorKind::UnknownSyntax(token_key.trim().to_string()),
+                ));
+            }
+        };
+
+        if self.bytes[self.pos..].starts_with(b"\r\n") {
+            self.pos += 2;
+        } else if self.bytes.get(self.pos) == Some(&b'\n') {
+            self.pos += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Parses nodes until `end`, which is the end of the source, or the closing brace or end tag of the block
+    /// being parsed.
+    fn parse_nodes(&mut self, end: BlockEnd) -> Result<Vec<Node>, TemplateError> {
+        let mut nodes: Vec<Node> = vec![];
+        let mut text: Vec<u8> = vec![];
+        let mut brace_count = 0;
+        let braces = matches!(end, BlockEnd::Brace(_));
+
+        while self.pos < self.bytes.len() {
+            let ch = self.bytes[self.pos];
+
+            if self.trim_next {
+                if ch.is_ascii_whitespace() {
+                    self.pos += 1;
+                    continue;
+                }
+
+                self.trim_next = false;
+            }
+
+            match ch {
+                // skip escape characters. \@ tokens will be displayed in raw text.
+                b'\\' => {
+                    self.pos += 1;
+
+                    if let Some(&ch) = self.bytes.get(self.pos) {
+                        text.push(ch);
+                        self.pos += 1;
+                    }
+                }
+                b'{' if braces => {
+                    brace_count += 1;
+                    text.push(ch);
+                    self.pos += 1;
+                }
+                b'}' if braces => {
+                    self.pos += 1;
+
+                    if brace_count == 0 {
+                        self.flush_text(&mut nodes, &mut text);
+
+                        if self.engine.trim_blocks() {
+                            self.strip_indent(self.pos - 1, &mut nodes);
+                            self.skip_blank_line();
+                        }
+
+                        return Ok(nodes);
+                    }
+
+                    brace_count -= 1;
+                    text.push(ch);
+                }
+                // comments are dropped from the output.
+                b'@' if self.bytes[self.pos..].starts_with(COMMENT_START) => {
+                    let start = self.pos;
+                    let Some(len) = find(&self.bytes[start..], COMMENT_END, COMMENT_START.len())
+                    else {
+                        return Err(self.error(start, TemplateErrorKind::UnterminatedComment));
+                    };
+
+                    self.pos = start + len + COMMENT_END.len();
+                }
+                // verbatim regions are copied to the output as is, without looking for tokens or braces.
+                b'@' if self.bytes[self.pos..].starts_with(VERBATIM_START) => {
+                    let start = self.pos;
+                    let content_start = start + VERBATIM_START.len();
+                    let Some(len) = find(&self.bytes[content_start..], VERBATIM_END, 0) else {
+                        return Err(self.error(
+                            start,
+                            TemplateErrorKind::MissingEndTag(String::from("endverbatim")),
+                        ));
+                    };
+
+                    text.extend_from_slice(&self.bytes[content_start..content_start + len]);
+                    self.pos = content_start + len + VERBATIM_END.len();
+                }
+                b'@' => {
+                    // the context must be up to date before the token picks its escaping.
+                    self.flush_text(&mut nodes, &mut text);
+                    let tag_start = self.pos;
+                    let trim_before = self.bytes.get(tag_start + 1) == Some(&b'-');
+
+                    if let BlockEnd::Tags(start, end_tags) = end {
+                        if self.at_end_tag(end_tags) {
+                            if trim_before {
+                                trim_text_end(&mut nodes);
+                            } else if self.engine.trim_blocks() {
+                                self.strip_indent(tag_start, &mut nodes);
+                            }
+
+                            return Ok(nodes);
+                        }
+
+                        // an end tag for an enclosing block means this block was never closed.
+                        if self.at_end_tag(&END_TAGS) {
+                            let end_tag = end_tags.last().copied().unwrap_or_default();
+                            return Err(self.error(
+                                start,
+                                TemplateErrorKind::MissingEndTag(end_tag.to_string()),
+                            ));
+                        }
+                    }
+
+                    match self.parse_token()? {
+                        Some(node) => {
+                            if trim_before {
+                                trim_text_end(&mut nodes);
+                            } else if self.engine.trim_blocks() && self.is_block(&node) {
+                                self.strip_indent(tag_start, &mut nodes);
+                            }
+
+                            nodes.push(node);
+                        }
+                        None => {
+                            text.push(ch);
+                            self.pos += 1;
+                        }
+                    }
+                }
+                _ => {
+                    text.push(ch);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        match end {
+            BlockEnd::Eof => {}
+            BlockEnd::Brace(start) => {
+                return Err(self.error(start, TemplateErrorKind::UnterminatedBlock));
+            }
+            BlockEnd::Tags(start, end_tags) => {
+                let end_tag = end_tags.last().copied().unwrap_or_default();
+                return Err(
+                    self.error(start, TemplateErrorKind::MissingEndTag(end_tag.to_string()))
+                );
+            }
+        }
+
+        self.flush_text(&mut nodes, &mut text);
+        Ok(nodes)
+    }
+
+    /// Moves the pending text into the node list, joining it with a directly preceding text node.
+    fn flush_text(&mut self, nodes: &mut Vec<Node>, text: &mut Vec<u8>) {
+        if text.is_empty() {
+            return;
+        }
+
+        self.context.advance(text);
+        let text_str = String::from_utf8_lossy(text);
+
+        match nodes.last_mut() {
+            Some(Node::Text(last)) => last.push_str(&text_str),
+            _ => nodes.push(Node::Text(text_str.into_owned())),
+        }
+
+        text.clear();
+    }
+
+    /// The escaping for a value written at the current position, moving the context past the value.
+    fn value_escape(&mut self) -> Escape {
+        let escape = self.context.escape();
+        self.context.after_value();
+        escape
+    }
+
+    /// Parses the token starting at the current `@`. Returns None, without moving, if the `@` is not followed by
+    /// `[token type]:` and should be treated as text.
+    fn parse_token(&mut self) -> Result<Option<Node>, TemplateError> {
+        let start = self.pos;
+        self.pos += 1;
+        self.skip_trim_marker();
+
+        let Some(token_type) = self.parse_token_type() else {
+            self.pos = start;
+            return Ok(None);
+        };
+        let token_type = token_type.to_lowercase();
+
+        if token_type == "parent" && self.bytes.get(self.pos) == Some(&b';') {
+            if !self.blocks.iter().any(|(_, open)| *open) {
+                return Err(self.error(start, TemplateErrorKind::UnexpectedToken(token_type)));
+            }
+
+            self.parse_token_end();
+
+            // while a block overriding the layout's block is parsed again, the layout's content stands in here.
+            let open_block = self.blocks.iter().rev().find(|(_, open)| *open);
+
+            if let Some((block_start, block_end)) =
+                open_block.and_then(|(name, _)| self.layout_blocks.get(name))
+            {
+                if self.context != *block_start {
+                    return Err(self.error(
+                        start,
+                        TemplateErrorKind::ContextMismatch(String::from(
+                            "`@parent` is not in the same HTML context as the start of the block",
+                        )),
+                    ));
+                }
+
+                self.context = block_end.clone();
+            }
+
+            return Ok(Some(Node::Parent));
+        }
+
+        if self.bytes.get(self.pos) != Some(&b':') {
+            // keyless tokens are only valid directly after the block they belong to.
+            if KEYLESS_TOKENS.contains(&token_type.as_str())
+                && matches!(self.bytes.get(self.pos), Some(b'{' | b';'))
+            {
+                return Err(self.error(start, TemplateErrorKind::UnexpectedToken(token_type)));
+            }
+
+            self.pos = start;
+            return Ok(None);
+        }
+
+        self.pos += 1;
+        let key_start = self.pos;
+        let token_key = self.parse_token_key(start)?;
+
+        let node = match token_type.as_str() {
+            "value" => Node::Value {
+                expr: self.parse_expr(&token_key, key_start)?,
+                escape: self.value_escape(),
+            },
+            "raw" => {
+                let expr = self.parse_expr(&token_key, key_start)?;
+                self.context.after_value();
+
+                Node::Value {
+                    expr,
+                    escape: Escape::Raw,
+                }
+            }
+            "forvalue" => Node::Value {
+                expr: Expr::Path(self.parse_loop_path(&token_key, key_start)?),
+                escape: self.value_escape(),
+            },
+            "if" => {
+                let cond = self.parse_expr(&token_key, key_start)?;
+                self.parse_if(start, cond)?
+            }
+            "forif" => {
+                let cond = Expr::Path(self.parse_loop_path(&token_key, key_start)?);
+                self.parse_if(start, cond)?
+            }
+            "for" => {
+                let (binding, source) = match split_binding(&token_key) {
+                    Some((binding, source)) => (Some(binding), source),
+                    None => (None, token_key.as_str()),
+                };
+
+                let source = self.parse_expr(source, key_start)?;
+                self.parse_for(start, binding, source)?
+            }
+            "forfor" => {
+                let source = Expr::Path(self.parse_loop_path(&token_key, key_start)?);
+                self.parse_for(start, None, source)?
+            }
+            "include" => {
+                let name = token_key.trim();
+                let context = self.context.clone();
+
+                Node::Include {
+                    nodes: self.parse_child(start, name, context)?,
+                    name: name.to_string(),
+                }
+            }
+            "extends" => {
+                if self.depth > 0 || self.extends.is_some() {
+                    return Err(self.error(start, TemplateErrorKind::UnexpectedToken(token_type)));
+                }
+
+                let name = token_key.trim().to_string();
+                self.extends = Some((name.clone(), start));
+                Node::Extends { name }
+            }
+            "macro" => {
+                let (name, params) = split_call(&token_key)
+                    .filter(|(name, params)| {
+                        is_identifier(name)
+                            && (params.trim().is_empty()
+                                || params.split(',').all(|param| is_identifier(param.trim())))
+                    })
+                    .ok_or_else(|| {
+                        self.error(
+                            key_start,
+                            TemplateErrorKind::InvalidExpression(format!(
+                                "Invalid macro definition `{}`!",
+                                token_key
+                            )),
+                        )
+                    })?;
+                let params: Vec<String> = params
+                    .split(',')
+                    .map(|param| param.trim().to_string())
+                    .filter(|param| !param.is_empty())
+                    .collect();
+                let name = name.to_string();
+
+                // a macro's output may only be placed in HTML text, so its body starts and ends there.
+                let outer = std::mem::take(&mut self.context);
+                let body = self.parse_closed_block(start, "endmacro")?;
+                let ends_in_text = self.context.is_text();
+                self.context = outer;
+
+                if !ends_in_text {
+                    return Err(self.error(
+                        start,
+                        TemplateErrorKind::ContextMismatch(format!(
+                            "macro `{}` does not end in HTML text",
+                            name
+                        )),
+                    ));
+                }
+
+                self.define_macro(
+                    start,
+                    name.clone(),
+                    Macro { params, body },
+                    self.name.to_string(),
+                )?;
+                Node::Macro { name }
+            }
+            "call" => {
+                let (name, args) = split_call(&token_key)
+                    .filter(|(name, _)| is_identifier(name))
+                    .ok_or_else(|| {
+                        self.error(
+                            key_start,
+                            TemplateErrorKind::InvalidExpression(format!(
+                                "Invalid macro call `{}`!",
+                                token_key
+                            )),
+                        )
+                    })?;
+
+                if !self.context.is_text() {
+                    return Err(self.error(
+                        start,
+                        TemplateErrorKind::ContextMismatch(String::from(
+                            "`@call` can only be used in HTML text, outside of tags, scripts and styles",
+                        )),
+                    ));
+                }
+
+                Node::Call {
+                    name: name.to_string(),
+                    args: Expr::parse_list(args, self.engine)
+                        .map_err(|kind| self.error(key_start, kind))?,
+                }
+            }
+            "block" => {
+                let name = token_key.trim().to_string();
+
+                if !is_identifier(&name) {
+                    return Err(self.error(
+                        key_start,
+                        TemplateErrorKind::InvalidExpression(format!(
+                            "Invalid block name `{}`!",
+                            name
+                        )),
+                    ));
+                }
+
+                if self.blocks.iter().any(|(block, _)| *block == name) {
+                    return Err(self.error(start, TemplateErrorKind::DuplicateBlock(name)));
+                }
+
+                let idx = self.blocks.len();
+                self.blocks.push((name.clone(), true));
+                self.block_bodies.insert(
+                    name.clone(),
+                    BlockBody {
+                        token_start: start,
+                        body_start: self.pos,
+                        trim_next: self.trim_next,
+                    },
+                );
+                let context = self.context.clone();
+                let body = self.parse_closed_block(start, "endblock")?;
+                self.blocks[idx].1 = false;
+                self.block_contexts
+                    .insert(name.clone(), (context, self.context.clone()));
+
+                Node::Block { name, body }
+            }
+            "elseif" | "forelseif" => {
+                return Err(self.error(start, TemplateErrorKind::UnexpectedToken(token_type)));
+            }
+            _ => match self.engine.custom_token(&token_type) {
+                Some(token) if token.block => Node::Custom {
+                    escape: self.context.escape(),
+                    body: self.parse_closed_block(start, &format!("end{}", token_type))?,
+                    name: token_type,
+                    key: token_key,
+                },
+                Some(_) => Node::Custom {
+                    escape: self.value_escape(),
+                    body: vec![],
+                    name: token_type,
+                    key: token_key,
+                },
+                None => {
+                    return Err(self.error(start, TemplateErrorKind::UnknownTokenType(token_type)));
+                }
+            },
+        };
+
+        Ok(Some(node))
+    }
+
+    /// Reads the token type following an `@`, leaving the position on the character after it.
+    fn parse_token_type(&mut self) -> Option<String> {
+        let start = self.pos;
+
+        while let Some(&byte) = self.bytes.get(self.pos)
+            && (byte.is_ascii_alphanumeric() || byte == b'_')
+        {
+            self.pos += 1;
+        }
+
+        if self.pos == start || self.pos - start >= MAX_TOKEN_LEN {
+            return None;
+        }
+
+        Some(String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned())
+    }
+
+    fn parse_loop_path(&self, key: &str, key_start: usize) -> Result<Path, TemplateError> {
+        Path::parse_loop(key).map_err(|msg| self.error(key_start, TemplateErrorKind::BadIndex(msg)))
+    }
+
+    fn parse_expr(&self, key: &str, key_start: usize) -> Result<Expr, TemplateError> {
+        Expr::parse_with(key, self.engine).map_err(|kind| self.error(key_start, kind))
+    }
+
+    /// Loads and parses an included template or layout, starting in the supplied HTML context. The text after
+    /// the token continues in the context the loaded template ends in.
+    fn parse_child(
+        &mut self,
+        token_start: usize,
+        name: &str,
+        context: HtmlContext,
+    ) -> Result<Vec<Node>, TemplateError> {
+        if self.includes.iter().any(|include| include == name) {
+            let chain = [self.includes.as_slice(), &[name.to_string()]]
+                .concat()
+                .join(" -> ");
+
+            return Err(self.error(token_start, TemplateErrorKind::IncludeCycle(chain)));
+        }
+
+        let source = self
+            .engine
+            .load_source(name)
+            .map_err(|msg| self.error(token_start, TemplateErrorKind::IncludeFailed(msg)))?;
+
+        let mut includes = self.includes.clone();
+        includes.push(name.to_string());
+
+        let mut parser = Parser::new(self.engine, name, &source, context, includes);
+        let nodes = parser.parse_template()?;
+        self.context = parser.context;
+        self.block_contexts.extend(parser.block_contexts);
+
+        for (macro_name, def) in parser.macros {
+            let source = parser.macro_sources.remove(&macro_name).unwrap_or_default();
+            self.define_macro(token_start, macro_name, def, source)?;
+        }
+
+        Ok(nodes)
+    }
+
+    fn define_macro(
+        &mut self,
+        token_start: usize,
+        name: String,
+        def: Macro,
+        source: String,
+    ) -> Result<(), TemplateError> {
+        match self.macro_sources.get(&name) {
+            // the same template included again defines the same macro.
+            Some(existing) if *existing == source && source != self.name => return Ok(()),
+            Some(_) => {
+                return Err(self.error(token_start, TemplateErrorKind::DuplicateMacro(name)));
+            }
+            None => {}
+        }
+
+        self.macros.insert(name.clone(), def);
+        self.macro_sources.insert(name, source);
+        Ok(())
+    }
+
+    /// Parses the body of an if, along with any `@elseif`, `@forelseif` or `@else` chained after it.
+    /// Every branch starts in the HTML context at the `@if`, and all of them must end in the same context, an `@if`
+    /// without an `@else` ending where it starts.
+    fn parse_if(&mut self, token_start: usize, cond: Expr) -> Result<Node, TemplateError> {
+        let start_context = self.context.clone();
+        let body = self.parse_block(token_start, &IF_ENDS)?;
+        let body_context = std::mem::replace(&mut self.context, start_context);
+        let else_body = self.parse_else(token_start)?;
+
+        if self.context != body_context {
+            return Err(self.error(
+                token_start,
+                TemplateErrorKind::ContextMismatch(String::from(
+                    "the branches of the `@if` end in different parts of the HTML",
+                )),
+            ));
+        }
+
+        Ok(Node::If {
+            cond,
+            body,
+            else_body,
+        })
+    }
+
+    /// Parses the body of a for, along with any `@empty` chained after it.
+    fn parse_for(
+        &mut self,
+        token_start: usize,
+        binding: Option<LoopBinding>,
+        source: Expr,
+    ) -> Result<Node, TemplateError> {
+        // the body repeats, so it must end in the context it starts in, as must the `@empty` body.
+        let start_context = self.context.clone();
+        let body = self.parse_block(token_start, &FOR_ENDS)?;
+        let body_context = std::mem::replace(&mut self.context, start_context.clone());
+        let empty_body = self.parse_empty()?;
+        self.parse_end_tag(token_start, "endfor")?;
+
+        if body_context != start_context || self.context != start_context {
+            return Err(self.error(
+                token_start,
+                TemplateErrorKind::ContextMismatch(String::from(
+                    "the body of the `@for` does not end in the same part of the HTML it starts in",
+                )),
+            ));
+        }
+
+        Ok(Node::For {
+            binding,
+            source,
+            body,
+            empty_body,
+        })
+    }
+
+    /// Parses an else token directly following the body of an if. Only whitespace may sit between the two, and
+    /// it is dropped from the output. Returns an empty body, without moving, if there is no else token. With end
+    /// tags, this also reads the `@endif;` closing the whole chain.
+    fn parse_else(&mut self, if_start: usize) -> Result<Vec<Node>, TemplateError> {
+        let end = self.pos;
+        self.skip_whitespace();
+        let start = self.pos;
+
+        if self.bytes.get(start) == Some(&b'@') {
+            self.pos += 1;
+            self.skip_trim_marker();
+
+            if let Some(token_type) = self.parse_token_type() {
+                match (token_type.to_lowercase().as_str(), self.bytes.get(self.pos)) {
+                    ("else", Some(b'{' | b';')) => {
+                        self.parse_token_end();
+
+                        return self.parse_closed_block(start, "endif");
+                    }
+                    ("elseif", Some(b':')) => {
+                        self.pos += 1;
+                        let key_start = self.pos;
+                        let token_key = self.parse_token_key(start)?;
+                        let cond = self.parse_expr(&token_key, key_start)?;
+
+                        return Ok(vec![self.parse_if(start, cond)?]);
+                    }
+                    ("forelseif", Some(b':')) => {
+                        self.pos += 1;
+                        let key_start = self.pos;
+                        let token_key = self.parse_token_key(start)?;
+                        let cond = Expr::Path(self.parse_loop_path(&token_key, key_start)?);
+
+                        return Ok(vec![self.parse_if(start, cond)?]);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.pos = end;
+        self.parse_end_tag(if_start, "endif")?;
+        Ok(vec![])
+    }
+
+    /// Parses an `@empty` directly following the body of a for. Only whitespace may sit between the two, and it
+    /// is dropped from the output. Returns an empty body, without moving, if there is no empty token.
+    fn parse_empty(&mut self) -> Result<Vec<Node>, TemplateError> {
+        let end = self.pos;
+        self.skip_whitespace();
+        let start = self.pos;
+
+        if self.bytes.get(start) == Some(&b'@') {
+            self.pos += 1;
+            self.skip_trim_marker();
+
+            if let Some(token_type) = self.parse_token_type()
+                && token_type.eq_ignore_ascii_case("empty")
+                && let Some(b'{' | b';') = self.bytes.get(self.pos)
+            {
+                self.parse_token_end();
+
+                return self.parse_block(start, &["endfor"]);
+            }
+        }
+
+        self.pos = end;
+        Ok(vec![])
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    /// Reads the key up to the closing `;`, or up to the `{` of a block. A `;` or `{` inside a quoted string does
+    /// not end the key. Keys may not span lines.
+    fn parse_token_key(&mut self, token_start: usize) -> Result<String, TemplateError> {
+        let start = self.pos;
+        let mut quote: Option<u8> = None;
+
+        while self.pos < self.bytes.len()
+            && (quote.is_some() || (self.bytes[self.pos] != b';' && self.bytes[self.pos] != b'{'))
+        {
+            let byte = self.bytes[self.pos];
+
+            if byte == b'\n' {
+                return Err(self.error(token_start, TemplateErrorKind::UnterminatedToken));
+            }
+
+            if self.pos - start >= MAX_TOKEN_LEN {
+                return Err(self.error(start, TemplateErrorKind::KeyTooLong));
+            }
+
+            match quote {
+                Some(_) if byte == b'\\' => self.pos += 1,
+                Some(open) if byte == open => quote = None,
+                None if byte == b'"' || byte == b'\'' => quote = Some(byte),
+                _ => {}
+            }
+
+            self.pos += 1;
+        }
+
+        if self.pos >= self.bytes.len() {
+            return Err(self.error(token_start, TemplateErrorKind::UnterminatedToken));
+        }
+
+        let token_key = String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned();
+        self.parse_token_end();
+
+        Ok(token_key)
+    }
+
+    /// Reads the `;` ending a token, along with a `-` after it that drops the whitespace following the token.
+    /// The `-` is only read when whitespace or a `{` follows it, so `@value:from;-@value:to;` keeps its dash.
+    fn parse_token_end(&mut self) {
+        if self.bytes.get(self.pos) != Some(&b';') {
+            return;
+        }
+
+        self.pos += 1;
+
+        if self.bytes.get(self.pos) == Some(&b'-')
+            && self
+                .bytes
+                .get(self.pos + 1)
+                .is_none_or(|&byte| byte.is_ascii_whitespace() || byte == b'{')
+        {
+            self.pos += 1;
+            self.trim_next = true;
+        }
+    }
+
+    /// Skips the `-` directly after an `@`, which drops the whitespace before the token.
+    fn skip_trim_marker(&mut self) {
+        if self.bytes.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips the rest of the line, including the line break, if it holds nothing but whitespace.
+    fn skip_blank_line(&mut self) {
+        let rest = &self.bytes[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&byte| !matches!(byte, b' ' | b'\t' | b'\r'))
+            .unwrap_or(rest.len());
+
+        match rest.get(len) {
+            Some(b'\n') => self.pos += len + 1,
+            None => self.pos += len,
+            Some(_) => {}
+        }
+    }
+
+    /// Drops the indentation before the block tag at `tag_start`, if nothing else sits before the tag on its line.
+    fn strip_indent(&self, tag_start: usize, nodes: &mut Vec<Node>) {
+        let indent = self.bytes[..tag_start]
+            .iter()
+            .rev()
+            .take_while(|&&byte| byte == b' ' || byte == b'\t')
+            .count();
+
+        if tag_start > indent && self.bytes[tag_start - indent - 1] != b'\n' {
+            return;
+        }
+
+        if let Some(Node::Text(text)) = nodes.last_mut() {
+            text.truncate(text.trim_end_matches([' ', '\t']).len());
+
+            if text.is_empty() {
+                nodes.pop();
+            }
+        }
+    }
+
+    /// Whether the node is a block token, whose tags `Engine::with_trim_blocks` trims around.
+    fn is_block(&self, node: &Node) -> bool {
+        match node {
+            Node::If { .. } | Node::For { .. } | Node::Block { .. } | Node::Macro { .. } => true,
+            Node::Custom { name, .. } => self
+                .engine
+                .custom_token(name)
+                .is_some_and(|token| token.block),
+            _ => false,
+        }
+    }
+
+    /// Parses the body following the block token that started at `token_start`. With braces, the body is
+    /// wrapped in `{...}`. With end tags, the body runs up to any of the supplied end tags, which are left for the
+    /// caller to read.
+    fn parse_block(
+        &mut self,
+        token_start: usize,
+        end_tags: &[&str],
+    ) -> Result<Vec<Node>, TemplateError> {
+        let end = match self.syntax {
+            BlockSyntax::Braces => {
+                self.skip_whitespace();
+
+                if self.bytes.get(self.pos) != Some(&b'{') {
+                    return Err(self.error(token_start, TemplateErrorKind::MissingBlock));
+                }
+
+                self.pos += 1;
+                BlockEnd::Brace(token_start)
+            }
+            BlockSyntax::EndTags => BlockEnd::Tags(token_start, end_tags),
+        };
+
+        if self.engine.trim_blocks() {
+            self.skip_blank_line();
+        }
+
+        self.depth += 1;
+        let nodes = self.parse_nodes(end);
+        self.depth -= 1;
+
+        nodes
+    }
+
+    /// Parses the body of a block that only ends with the supplied end tag, such as `@endblock;`.
+    fn parse_closed_block(
+        &mut self,
+        token_start: usize,
+        end_tag: &str,
+    ) -> Result<Vec<Node>, TemplateError> {
+        let body = self.parse_block(token_start, &[end_tag])?;
+        self.parse_end_tag(token_start, end_tag)?;
+
+        Ok(body)
+    }
+
+    /// Reads the end tag closing the block token that started at `token_start`. Does nothing with braces.
+    fn parse_end_tag(&mut self, token_start: usize, end_tag: &str) -> Result<(), TemplateError> {
+        if self.syntax == BlockSyntax::Braces {
+            return Ok(());
+        }
+
+        if self.at_end_tag(&[end_tag]) {
+            self.pos += 1;
+            self.skip_trim_marker();
+            self.parse_token_type();
+
+            if self.bytes.get(self.pos) == Some(&b';') {
+                self.parse_token_end();
+
+                if self.engine.trim_blocks() {
+                    self.skip_blank_line();
+                }
+
+                return Ok(());
+            }
+        }
+
+        Err(self.error(
+            token_start,
+            TemplateErrorKind::MissingEndTag(end_tag.to_string()),
+        ))
+    }
+
+    /// Whether the `@` at the current position starts one of the supplied end tags. Does not move.
+    fn at_end_tag(&mut self, end_tags: &[&str]) -> bool {
+        let start = self.pos;
+        self.pos += 1;
+        self.skip_trim_marker();
+
+        let found = self.parse_token_type().is_some_and(|token_type| {
+            end_tags
+                .iter()
+                .any(|end_tag| token_type.eq_ignore_ascii_case(end_tag))
+        });
+
+        self.pos = start;
+        found
+    }
+}
+
+/// Drops the whitespace at the end of the last text node, for a token starting with `@-`.
+fn trim_text_end(nodes: &mut Vec<Node>) {
+    if let Some(Node::Text(text)) = nodes.last_mut() {
+        text.truncate(text.trim_end().len());
+
+        if text.is_empty() {
+            nodes.pop();
+        }
+    }
+}
+
+/// Gathers the blocks of a template that extends a layout, including blocks nested inside other nodes.
+fn collect_blocks(nodes: &mut [Node], blocks: &mut HashMap<String, Vec<Node>>) {
+    for node in nodes {
+        if let Node::Block { name, body } = node {
+            blocks.insert(name.clone(), body.clone());
+        }
+
+        for children in node.children_mut() {
+            collect_blocks(children, blocks);
+        }
+    }
+}
+
+/// Replaces the layout's blocks with the extending template's blocks of the same name.
+fn override_blocks(nodes: &mut [Node], blocks: &HashMap<String, Vec<Node>>) {
+    for node in nodes {
+        for children in node.children_mut() {
+            override_blocks(children, blocks);
+        }
+
+        if let Node::Block { name, body } = node
+            && let Some(child_body) = blocks.get(name)
+        {
+            let mut child_body = child_body.clone();
+            replace_parent(&mut child_body, body);
+            *body = child_body;
+        }
+    }
+}
+
+/// Replaces each `@parent` with the layout's content of the block, leaving nested blocks alone.
+fn replace_parent(nodes: &mut Vec<Node>, parent: &[Node]) {
+    let mut replaced = Vec::with_capacity(nodes.len());
+
+    for mut node in nodes.drain(..) {
+        match node {
+            Node::Parent => replaced.extend_from_slice(parent),
+            Node::Block { .. } => replaced.push(node),
+            _ => {
+                for children in node.children_mut() {
+                    replace_parent(children, parent);
+                }
+
+                replaced.push(node);
+            }
+        }
+    }
+
+    *nodes = replaced;
+}
+
+/// Splits a `name in source` or `(key, value) in source` for key into its loop variables and the source.
+fn split_binding(key: &str) -> Option<(LoopBinding, &str)> {
+    let (binding, source) = key.split_once(" in ")?;
+    let binding = binding.trim();
+
+    if let Some(pair) = binding
+        .strip_prefix('(')
+        .and_then(|pair| pair.strip_suffix(')'))
+    {
+        let (key_name, value_name) = pair.split_once(',')?;
+        let (key_name, value_name) = (key_name.trim(), value_name.trim());
+
+        if !is_identifier(key_name) || !is_identifier(value_name) {
+            return None;
+        }
+
+        return Some((
+            LoopBinding::Pair(key_name.to_string(), value_name.to_string()),
+            source,
+        ));
+    }
+
+    if !is_identifier(binding) {
+        return None;
+    }
+
+    Some((LoopBinding::Value(binding.to_string()), source))
+}
+
+/// The position of the first `needle` in `haystack` at or after `from`.
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|idx| idx + from)
+}
+
+/// Splits a `name(list)` key into the name and the contents of the parentheses.
+fn split_call(key: &str) -> Option<(&str, &str)> {
+    let (name, rest) = key.trim().split_once('(')?;
+
+    Some((name.trim(), rest.strip_suffix(')')?))
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|ch: char| ch.is_ascii_digit())
+        && name.chars().all(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::engine::Truthiness;
+    use super::super::error::RenderError;
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_compile_nodes() {
+        let template = Template::compile("Hi @value:name;!@if:ok;{yes}").unwrap();
+
+        assert_eq!(
+            template.nodes,
+            vec![
+                Node::Text(String::from("Hi ")),
+                Node::Value {
+                    expr: Expr::Path(Path::parse("name").unwrap()),
+                    escape: Escape::Html,
+                },
+                Node::Text(String::from("!")),
+                Node::If {
+                    cond: Expr::Path(Path::parse("ok").unwrap()),
+                    body: vec![Node::Text(String::from("yes"))],
+                    else_body: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_nested_braces() {
+        let template = Template::compile("@if:ok;{a{b}c}d").unwrap();
+        let result = template.render_value(&json!({"ok": true}));
+
+        assert_eq!(result, "a{b}cd");
+    }
+
+    #[test]
+    fn test_compile_plain_at() {
+        let template = Template::compile("mail me@example.com @ home").unwrap();
+        let result = template.render_value(&json!({}));
+
+        assert_eq!(result, "mail me@example.com @ home");
+    }
+
+    #[test]
+    fn test_compile_bad_index() {
+        let error = Template::compile("@value:users[x];").unwrap_err();
+
+        assert!(matches!(error.kind, TemplateErrorKind::BadIndex(_)));
+        assert_eq!((error.line, error.column), (1, 8));
+    }
+
+    #[test]
+    fn test_compile_unterminated_block() {
+        let error =
+            Template::compile_named("page.html", "<ul>\n  @for:users;{\n<li></li>\n").unwrap_err();
+
+        assert_eq!(error.kind, TemplateErrorKind::UnterminatedBlock);
+        assert_eq!(error.name, "page.html");
+        assert_eq!((error.line, error.column), (2, 3));
+        assert_eq!(error.snippet, "  @for:users;{");
+    }
+
+    #[test]
+    fn test_compile_unknown_token_type() {
+        let error = Template::compile("a @vaule:name;").unwrap_err();
+
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnknownTokenType(String::from("vaule"))
+        );
+        assert_eq!(error.column, 3);
+    }
+
+    #[test]
+    fn test_compile_unterminated_token() {
+        let error = Template::compile("@value:name\n;").unwrap_err();
+
+        assert_eq!(error.kind, TemplateErrorKind::UnterminatedToken);
+    }
+
+    #[test]
+    fn test_compile_key_too_long() {
+        let source = format!("@value:{};", "a".repeat(MAX_TOKEN_LEN + 1));
+        let error = Template::compile(&source).unwrap_err();
+
+        assert_eq!(error.kind, TemplateErrorKind::KeyTooLong);
+    }
+
+    #[test]
+    fn test_compile_missing_block() {
+        let error = Template::compile("@if:ok; nothing here").unwrap_err();
+
+        assert_eq!(error.kind, TemplateErrorKind::MissingBlock);
+    }
+
+    #[test]
+    fn test_render_escaped() {
+        let template = Template::compile("@value:name;|@raw:name;").unwrap();
+        let result = template.render_value(&json!({"name": "<b>Bob</b>"}));
+
+        assert_eq!(result, "&lt;b&gt;Bob&lt;/b&gt;|<b>Bob</b>");
+    }
+
+    #[test]
+    fn test_render_context_escaped() {
+        let template = Template::compile(
+            "<a href=\"@value:url;?q=@value:q;\" onclick=\"go(@value:q;)\">@value:q;</a><script>var q = @value:q;;</script>",
+        )
+        .unwrap();
+        let result = template.render_value(&json!({"url": "javascript:alert(1)", "q": "a&b"}));
+
+        assert_eq!(
+            result,
+            "<a href=\"#blocked?q=a%26b\" onclick=\"go(&quot;a\\u0026b&quot;)\">a&amp;b</a><script>var q = \"a\\u0026b\";</script>"
+        );
+    }
+
+    #[test]
+    fn test_render_template_literal_escaped() {
+        let template = Template::compile("<script>var s = `@value:x;`;</script>").unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"x": "${alert(1)}"})),
+            "<script>var s = `\\u0024\\u007balert(1)}`;</script>"
+        );
+    }
+
+    #[test]
+    fn test_render_unicode_keys() {
+        let template =
+            Template::compile("@value:prénom;: @for:élément in éléments;{@value:élément;}")
+                .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"prénom": "Zoé", "éléments": ["à", "é"]})),
+            "Zoé: àé"
+        );
+    }
+
+    #[test]
+    fn test_render_split_url_blocked() {
+        let template = Template::compile("<a href=\"@value:a;@value:b;\">x</a>").unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"a": "java", "b": "script:alert(1)"})),
+            "<a href=\"java#blocked\">x</a>"
+        );
+    }
+
+    #[test]
+    fn test_render_branch_contexts() {
+        let template = Template::compile(
+            "<a @if:on;{class=\"on\"}@else{title=\"@value:t;\"} href=\"@value:u;\">x</a>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"on": false, "t": "\"", "u": "javascript:x"})),
+            "<a title=\"&quot;\" href=\"#blocked\">x</a>"
+        );
+
+        for source in [
+            "@if:a;{<a title=\"}@else{<script>var q = 1;}@value:x;",
+            "@if:a;{<script>}@value:x;",
+            "@if:a;{}@elseif:b;{<a href=\"}@else{}",
+            "@for:x in xs;{<a title=\"}\">",
+            "@for:x in xs;{}@empty{<style>}",
+        ] {
+            let error = Template::compile(source).unwrap_err();
+            assert!(
+                matches!(error.kind, TemplateErrorKind::ContextMismatch(_)),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_url_leading_whitespace_blocked() {
+        let template =
+            Template::compile("<a href=\" @value:u;\">x</a><a href=\"\n@value:u;\">y</a>").unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"u": "javascript:alert(1)"})),
+            "<a href=\" #blocked\">x</a><a href=\"\n#blocked\">y</a>"
+        );
+    }
+
+    #[test]
+    fn test_render_srcdoc_escaped() {
+        let template = Template::compile("<iframe srcdoc=\"@value:x;\"></iframe>").unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"x": "<img src=x onerror=alert(1)>"})),
+            "<iframe srcdoc=\"&amp;lt;img src=x onerror=alert(1)&amp;gt;\"></iframe>"
+        );
+    }
+
+    #[test]
+    fn test_render_script_comment_escaped() {
+        let template = Template::compile(
+            "<script>// don't\nvar a = @value:x;; /* it's */ var b = @value:x;;</script>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"x": "alert(1)"})),
+            "<script>// don't\nvar a = \"alert(1)\"; /* it's */ var b = \"alert(1)\";</script>"
+        );
+    }
+
+    #[test]
+    fn test_render_else_chain() {
+        let template = Template::compile("@if:a;{A}\n  @elseif:b;{B}\n  @else{C} after").unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"a": true, "b": true})),
+            "A after"
+        );
+        assert_eq!(
+            template.render_value(&json!({"a": false, "b": true})),
+            "B after"
+        );
+        assert_eq!(
+            template.render_value(&json!({"a": false, "b": false})),
+            "C after"
+        );
+    }
+
+    #[test]
+    fn test_render_forelseif() {
+        let template = Template::compile(
+            "@for:users;{@forif:0.admin;{admin}@forelseif:0.owner;{owner}@else;{user},}",
+        )
+        .unwrap();
+        let result = template.render_value(&json!({
+            "users": [{"admin": true}, {"owner": true}, {}]
+        }));
+
+        assert_eq!(result, "admin,owner,user,");
+    }
+
+    #[test]
+    fn test_render_if_expression() {
+        let template = Template::compile(
+            "@if:!user.is_admin && count > 0;{limited}@elseif:role == \"a;b{\";{quoted}@else{none}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"user": {"is_admin": false}, "count": 2})),
+            "limited"
+        );
+        assert_eq!(
+            template.render_value(&json!({"user": {"is_admin": true}, "role": "a;b{"})),
+            "quoted"
+        );
+    }
+
+    #[test]
+    fn test_compile_invalid_expression() {
+        let error = Template::compile("@if:a &&;{x}").unwrap_err();
+
+        assert!(matches!(
+            error.kind,
+            TemplateErrorKind::InvalidExpression(_)
+        ));
+        assert_eq!(error.column, 5);
+    }
+
+    #[test]
+    fn test_compile_unattached_else() {
+        let error = Template::compile("text @else{oops}").unwrap_err();
+
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnexpectedToken(String::from("else"))
+        );
+        assert_eq!(error.column, 6);
+    }
+
+    #[test]
+    fn test_render_named_loops() {
+        let template = Template::compile(
+            "@for:user in users;{@value:user.name;[@for:child in user.user_vec;{@if:child.active;{@value:child.name;@value:user.name;}}]}",
+        )
+        .unwrap();
+        let result = template.render_value(&json!({
+            "users": [
+                {"name": "A", "user_vec": [{"name": "a1", "active": true}, {"name": "a2", "active": false}]},
+                {"name": "B", "user_vec": []}
+            ]
+        }));
+
+        assert_eq!(result, "A[a1A]B[]");
+    }
+
+    #[test]
+    fn test_render_named_loop_shadows_modal() {
+        let template =
+            Template::compile("@value:name;:@for:name in names;{@value:name;,@forvalue:0;;}")
+                .unwrap();
+        let result = template.render_value(&json!({"name": "outer", "names": ["x", "y"]}));
+
+        assert_eq!(result, "outer:x,x;y,y;");
+    }
+
+    #[test]
+    fn test_render_loop_meta() {
+        let template = Template::compile(
+            "@for:user in users;{@value:loop.index;/@value:loop.length;:@value:user;@if:!loop.last;{, }}",
+        )
+        .unwrap();
+        let result = template.render_value(&json!({"users": ["a", "b", "c"]}));
+
+        assert_eq!(result, "1/3:a, 2/3:b, 3/3:c");
+    }
+
+    #[test]
+    fn test_render_loop_meta_parent() {
+        let template = Template::compile(
+            "@for:groups;{@forfor:0.items;{@value:loop.parent.index0;.@value:loop.index0;@if:loop.first;{*} }}",
+        )
+        .unwrap();
+        let result = template.render_value(&json!({"groups": [{"items": [1, 2]}, {"items": [3]}]}));
+
+        assert_eq!(result, "0.0* 0.1 1.0* ");
+    }
+
+    #[test]
+    fn test_render_object_pairs() {
+        let template = Template::compile(
+            "@for:(key, value) in settings;{@value:key;=@value:value;@if:!loop.last;{&}}",
+        )
+        .unwrap();
+        let result = template.render_value(&json!({
+            "settings": {"theme": "dark", "lang": "en", "beta": true}
+        }));
+
+        assert_eq!(result, "beta=true&lang=en&theme=dark");
+    }
+
+    #[test]
+    fn test_render_object_values_and_array_pairs() {
+        let template =
+            Template::compile("@for:v in map;{@value:v;}|@for:(i, v) in list;{@value:i;@value:v;}")
+                .unwrap();
+        let result = template.render_value(&json!({"map": {"b": 2, "a": 1}, "list": ["x", "y"]}));
+
+        assert_eq!(result, "12|0x1y");
+    }
+
+    #[test]
+    fn test_render_for_empty() {
+        let template = Template::compile(
+            "<ul>@for:user in users;{<li>@value:user;</li>}\n@empty{<li>No results</li>}</ul>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"users": ["a"]})),
+            "<ul><li>a</li></ul>"
+        );
+        assert_eq!(
+            template.render_value(&json!({"users": []})),
+            "<ul><li>No results</li></ul>"
+        );
+        assert_eq!(
+            template.render_value(&json!({})),
+            "<ul><li>No results</li></ul>"
+        );
+    }
+
+    #[test]
+    fn test_render_forfor_empty() {
+        let template =
+            Template::compile("@for:groups;{@forfor:0.users;{@forvalue:1;}@empty;{-}}").unwrap();
+        let result =
+            template.render_value(&json!({"groups": [{"users": ["a", "b"]}, {"users": []}]}));
+
+        assert_eq!(result, "ab-");
+    }
+
+    #[test]
+    fn test_render_value_filters() {
+        let template = Template::compile(
+            "<p title=\"@value:name|truncate(8);\">@value:name|upper;</p>@value:str_vec|join(\", \");@value:nickname|default(\"Anonymous\");",
+        )
+        .unwrap();
+        let result =
+            template.render_value(&json!({"name": "Alice <Smith>", "str_vec": ["a", "b"]}));
+
+        assert_eq!(
+            result,
+            "<p title=\"Alice...\">ALICE &lt;SMITH&gt;</p>a, bAnonymous"
+        );
+    }
+
+    #[test]
+    fn test_compile_unknown_filter() {
+        let error = Template::compile("@value:name|shout;").unwrap_err();
+
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnknownFilter(String::from("shout"))
+        );
+    }
+
+    /// A directory of templates for include tests, removed when the test ends.
+    struct TemplateDir(std::path::PathBuf);
+
+    impl TemplateDir {
+        fn path(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TemplateDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Writes the supplied files into a fresh directory for include tests.
+    fn template_dir(test_name: &str, files: &[(&str, &str)]) -> TemplateDir {
+        let dir = TemplateDir(std::env::temp_dir().join(format!(
+            "html_modal_{}_{}",
+            test_name,
+            std::process::id()
+        )));
+
+        for (file, source) in files {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn test_render_include() {
+        let dir = template_dir(
+            "include",
+            &[
+                (
+                    "page.html",
+                    "@include:partials/header.html;<main>@for:user in users;{@include:partials/user.html;}</main>",
+                ),
+                ("partials/header.html", "<header>@value:title;</header>"),
+                (
+                    "partials/user.html",
+                    "<p title=\"@value:user;\">@value:loop.index;</p>",
+                ),
+            ],
+        );
+        let engine = Engine::new().with_template_root(dir.path());
+        let template = engine.compile_file("page.html").unwrap();
+        let result = engine.render_value(&template, &json!({"title": "Users", "users": ["a\"b"]}));
+
+        assert_eq!(
+            result,
+            "<header>Users</header><main><p title=\"a&quot;b\">1</p></main>"
+        );
+    }
+
+    #[test]
+    fn test_render_include_macros() {
+        let dir = template_dir(
+            "include_macros",
+            &[
+                ("m.html", "@macro:card(x);{<b>@value:x;</b>}"),
+                ("h.html", "@include:m.html;<h1>@call:card(title);</h1>"),
+                ("other.html", "@macro:card(x);{<i>@value:x;</i>}"),
+            ],
+        );
+        let engine = Engine::new().with_template_root(dir.path());
+
+        for (source, expected) in [
+            (
+                "@include:h.html;@include:h.html;",
+                "<h1><b>T</b></h1><h1><b>T</b></h1>",
+            ),
+            ("@include:m.html;@include:h.html;", "<h1><b>T</b></h1>"),
+        ] {
+            let template = engine.compile(source).unwrap();
+            assert_eq!(
+                engine.render_value(&template, &json!({"title": "T"})),
+                expected
+            );
+        }
+
+        for source in [
+            "@include:m.html;@include:other.html;",
+            "@include:m.html;@macro:card(x);{}",
+        ] {
+            let error = engine.compile(source).unwrap_err();
+            assert_eq!(
+                error.kind,
+                TemplateErrorKind::DuplicateMacro(String::from("card"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile_include_cycle() {
+        let dir = template_dir(
+            "include_cycle",
+            &[
+                ("a.html", "@include:b.html;"),
+                ("b.html", "\n  @include:a.html;"),
+            ],
+        );
+        let error = Engine::new()
+            .with_template_root(dir.path())
+            .compile_file("a.html")
+            .unwrap_err();
+
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::IncludeCycle(String::from("a.html -> b.html -> a.html"))
+        );
+        assert_eq!(
+            (error.name.as_str(), error.line, error.column),
+            ("b.html", 2, 3)
+        );
+    }
+
+    #[test]
+    fn test_compile_include_failed() {
+        let dir = template_dir("include_failed", &[("a.html", "")]);
+        let engine = Engine::new().with_template_root(dir.path());
+
+        for source in ["@include:missing.html;", "@include:../a.html;"] {
+            let error = engine.compile(source).unwrap_err();
+            assert!(matches!(error.kind, TemplateErrorKind::IncludeFailed(_)));
+        }
+
+        let error = Template::compile("@include:a.html;").unwrap_err();
+        assert!(matches!(error.kind, TemplateErrorKind::IncludeFailed(_)));
+    }
+
+    #[test]
+    fn test_render_extends() {
+        let dir = template_dir(
+            "extends",
+            &[
+                (
+                    "layout.html",
+                    "<title>@block:title;{Site}</title><main>@block:content;{}</main>@block:footer;{(c)}",
+                ),
+                (
+                    "section.html",
+                    "@extends:layout.html;@block:title;{@parent; - Section}@block:content;{<nav></nav>@block:body;{}}",
+                ),
+                (
+                    "page.html",
+                    "@extends:section.html;\nignored @block:body;{@value:name;}@block:title;{@parent; - @value:name;}",
+                ),
+            ],
+        );
+        let engine = Engine::new().with_template_root(dir.path());
+        let template = engine.compile_file("page.html").unwrap();
+        let result = engine.render_value(&template, &json!({"name": "<Home>"}));
+
+        assert_eq!(
+            result,
+            "<title>Site - Section - &lt;Home&gt;</title><main><nav></nav>&lt;Home&gt;</main>(c)"
+        );
+    }
+
+    #[test]
+    fn test_render_extends_context_escaped() {
+        let dir = template_dir(
+            "extends_context",
+            &[
+                (
+                    "layout.html",
+                    "<script>var a = @block:js;{0};</script><a href=\"@block:u;{}\">x</a>",
+                ),
+                (
+                    "page.html",
+                    "@extends:layout.html;@block:js;{@value:x;}@block:u;{@value:u;}",
+                ),
+                (
+                    "broken.html",
+                    "@extends:layout.html;@block:u;{/\" onclick=\"}",
+                ),
+            ],
+        );
+        let engine = Engine::new().with_template_root(dir.path());
+        let template = engine.compile_file("page.html").unwrap();
+
+        assert_eq!(
+            engine.render_value(
+                &template,
+                &json!({"x": "alert(1)", "u": "javascript:alert(1)"})
+            ),
+            "<script>var a = \"alert(1)\";</script><a href=\"#blocked\">x</a>"
+        );
+        assert!(matches!(
+            engine.compile_file("broken.html").unwrap_err().kind,
+            TemplateErrorKind::ContextMismatch(_)
+        ));
+    }
+
+    #[test]
+    fn test_compile_block_errors() {
+        let error = Template::compile("@block:a;{}@block:a;{}").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::DuplicateBlock(String::from("a"))
+        );
+
+        let error = Template::compile("@parent;").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnexpectedToken(String::from("parent"))
+        );
+
+        let error = Template::compile("@if:x;{@extends:layout.html;}").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnexpectedToken(String::from("extends"))
+        );
+    }
+
+    #[test]
+    fn test_compile_extends_cycle() {
+        let dir = template_dir("extends_cycle", &[("a.html", "@extends:a.html;")]);
+        let error = Engine::new()
+            .with_template_root(dir.path())
+            .compile_file("a.html")
+            .unwrap_err();
+
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::IncludeCycle(String::from("a.html -> a.html"))
+        );
+    }
+
+    #[test]
+    fn test_render_macro_tree() {
+        let template = Template::compile(
+            "@macro:tree(users, depth);{@for:user in users;{<li>@value:depth;:@value:user.name;@if:user.user_vec;{<ul>@call:tree(user.user_vec, depth|length);</ul>}</li>}}<ul>@call:tree(user_vec, \"d\");</ul>",
+        )
+        .unwrap();
+        let modal = json!({"user_vec": [
+            {"name": "a", "user_vec": [{"name": "b", "user_vec": [{"name": "c", "user_vec": []}]}]},
+            {"name": "d", "user_vec": []},
+        ]});
+
+        assert_eq!(
+            Engine::new()
+                .with_truthiness(Truthiness::Loose)
+                .render_value(&template, &modal),
+            "<ul><li>d:a<ul><li>1:b<ul><li>0:c</li></ul></li></ul></li><li>d:d</li></ul>"
+        );
+    }
+
+    #[test]
+    fn test_render_macro_scope() {
+        let template = Template::compile(
+            "@for:user in users;{@call:card(user.name);@call:card();}@macro:card(name, compact);{[@value:name;|@value:user;|@value:compact;|@value:title;|@value:loop.index;]}",
+        )
+        .unwrap();
+        let result = template.render_value(&json!({"users": [{"name": "a"}], "title": "t"}));
+
+        assert_eq!(result, "[a|||t|][|||t|]");
+    }
+
+    #[test]
+    fn test_render_macro_errors() {
+        let engine = Engine::new();
+        let template =
+            Template::compile("@macro:forever();{@call:forever();}@call:forever();").unwrap();
+
+        assert_eq!(
+            engine.try_render_value(&template, &json!({})),
+            Err(RenderError::Macro {
+                name: String::from("forever"),
+                message: String::from("macro calls are nested too deeply"),
+            })
+        );
+
+        let template = Template::compile("@call:missing(1);").unwrap();
+        assert!(matches!(
+            engine.try_render_value(&template, &json!({})),
+            Err(RenderError::Macro { .. })
+        ));
+
+        let error = Template::compile("@macro:a();{}@macro:a(x);{}").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::DuplicateMacro(String::from("a"))
+        );
+
+        let error = Template::compile("@macro:a(x y);{}").unwrap_err();
+        assert!(matches!(
+            error.kind,
+            TemplateErrorKind::InvalidExpression(_)
+        ));
+    }
+
+    #[test]
+    fn test_render_macro_context() {
+        let template =
+            Template::compile("@macro:m(v);{<b>@value:v;</b>}<p>@call:m(x);</p>").unwrap();
+        assert_eq!(
+            template.render_value(&json!({"x": "<i>"})),
+            "<p><b>&lt;i&gt;</b></p>"
+        );
+
+        for source in [
+            "@macro:m(v);{@value:v;}<script>var a = @call:m(x);;</script>",
+            "@macro:m(v);{@value:v;}<a href=\"@call:m(u);\">x</a>",
+            "@macro:m();{<a title=\"}@call:m();",
+        ] {
+            let error = Template::compile(source).unwrap_err();
+            assert!(
+                matches!(error.kind, TemplateErrorKind::ContextMismatch(_)),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_end_tags() {
+        let template = Template::compile(
+            "@syntax:endtags;\n@if:ok;<script>if (a) { go(@value:n;); }</script>@elseif:n > 1;{many}@else;}none{@endif;|@for:x in xs;(@value:x;)@empty;-@endfor;",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"ok": true, "n": "1", "xs": [1, 2]})),
+            "<script>if (a) { go(\"1\"); }</script>|(1)(2)"
+        );
+        assert_eq!(
+            template.render_value(&json!({"ok": false, "n": 2, "xs": []})),
+            "{many}|-"
+        );
+        assert_eq!(
+            template.render_value(&json!({"ok": false, "n": 0})),
+            "}none{|-"
+        );
+    }
+
+    #[test]
+    fn test_render_end_tags_nested() {
+        let engine = Engine::new().with_block_syntax(BlockSyntax::EndTags);
+        let template = engine
+            .compile(
+                "@macro:item(x);<li>@value:x;</li>@endmacro;@block:list;@for:x in xs;@if:x > 1;@call:item(x);@endif;@endfor;@endblock;",
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.render_value(&template, &json!({"xs": [1, 2, 3]})),
+            "<li>2</li><li>3</li>"
+        );
+
+        let template = engine.compile("@syntax:braces;@if:ok;{{yes}}").unwrap();
+        assert_eq!(
+            engine.render_value(&template, &json!({"ok": true})),
+            "{yes}"
+        );
+    }
+
+    #[test]
+    fn test_compile_end_tag_errors() {
+        let error =
+            Template::compile("@syntax:endtags;\n@if:ok;\n@for:x in xs;x@endif;").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::MissingEndTag(String::from("endfor"))
+        );
+        assert_eq!((error.line, error.column), (3, 1));
+
+        let error = Template::compile("@syntax:endtags;@if:ok;yes").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::MissingEndTag(String::from("endif"))
+        );
+
+        let error = Template::compile("@syntax:endtags;@endfor;").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnexpectedToken(String::from("endfor"))
+        );
+
+        let error = Template::compile("@syntax:tabs;").unwrap_err();
+        assert_eq!(
+            error.kind,
+            TemplateErrorKind::UnknownSyntax(String::from("tabs"))
+        );
+    }
+
+    #[test]
+    fn test_render_comments() {
+        let template = Template::compile("a@* @value:secret; { *@b@if:ok;{@*}*@c}\\@* d").unwrap();
+
+        assert_eq!(template.render_value(&json!({"ok": true})), "abc@* d");
+        assert_eq!(
+            Template::compile("a @* open").unwrap_err().kind,
+            TemplateErrorKind::UnterminatedComment
+        );
+    }
+
+    #[test]
+    fn test_render_verbatim() {
+        let template = Template::compile(
+            "@if:ok;{@verbatim;<script type=\"text/x-template\">{{ @value:name; }} } \\@</script>@endverbatim;@value:name;}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"ok": true, "name": "<a>"})),
+            "<script type=\"text/x-template\">{{ @value:name; }} } \\@</script>&lt;a&gt;"
+        );
+        assert_eq!(
+            Template::compile("@verbatim; {").unwrap_err().kind,
+            TemplateErrorKind::MissingEndTag(String::from("endverbatim"))
+        );
+        assert_eq!(
+            Template::compile("@endverbatim;").unwrap_err().kind,
+            TemplateErrorKind::UnexpectedToken(String::from("endverbatim"))
+        );
+    }
+
+    #[test]
+    fn test_render_trim_markers() {
+        let template = Template::compile(
+            "<ul>\n  @-for:user in users;-{\n    <li>@value:user;</li>\n  @-value:\"\";-\n  }\n</ul> @value:from;-@value:to;",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"users": ["a", "b"], "from": 1, "to": 5})),
+            "<ul><li>a</li><li>b</li>\n</ul> 1-5"
+        );
+
+        let template =
+            Template::compile("@syntax:endtags;\n@if:ok;-\n  yes\n@-else;-\n  no\n@-endif;-\n.")
+                .unwrap();
+
+        assert_eq!(template.render_value(&json!({"ok": true})), "yes.");
+        assert_eq!(template.render_value(&json!({"ok": false})), "no.");
+    }
+
+    #[test]
+    fn test_render_trim_blocks() {
+        let engine = Engine::new().with_trim_blocks(true);
+        let template = engine
+            .compile(
+                "<ul>\n  @for:user in users;{\n    @if:user.admin;{\n    <li>@value:user.name; *</li>\n    }\n    @else{\n    <li>@value:user.name;</li>\n    }\n  }\n  @empty{\n  <li>none</li>\n  }\n</ul>\n",
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.render_value(
+                &template,
+                &json!({"users": [{"name": "a", "admin": true}, {"name": "b", "admin": false}]})
+            ),
+            "<ul>\n    <li>a *</li>\n    <li>b</li>\n</ul>\n"
+        );
+        assert_eq!(
+            engine.render_value(&template, &json!({"users": []})),
+            "<ul>\n  <li>none</li>\n</ul>\n"
+        );
+
+        let engine = engine.with_block_syntax(BlockSyntax::EndTags);
+        let template = engine
+            .compile(
+                "name,admin\n@for:user in users;\n@value:user.name;,@value:user.admin;\n@endfor;\n",
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.render_value(
+                &template,
+                &json!({"users": [{"name": "a", "admin": true}, {"name": "b", "admin": false}]})
+            ),
+            "name,admin\na,true\nb,false\n"
+        );
+        assert_eq!(
+            Template::compile("@if:ok;{\nyes\n}\n")
+                .unwrap()
+                .render_value(&json!({"ok": true})),
+            "\nyes\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_ranges_and_slices() {
+        let template = Template::compile(
+            "@for:user in users[0..2];{@value:user;,}|@value:users[-1];|@for:page in range(1, pages + 1);{[@value:page;]}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render_value(&json!({"users": ["a", "b", "c"], "pages": 3})),
+            "a,b,|c|[1][2][3]"
+        );
+        assert_eq!(
+            Engine::new()
+                .try_render_value(
+                    &Template::compile("@for:i in range(\"x\");{}").unwrap(),
+                    &json!({})
+                )
+                .unwrap_err(),
+            RenderError::Function {
+                name: String::from("range"),
+                message: String::from("expected an integer, found \"x\""),
+            }
+        );
+    }
+
+    #[test]
+    fn test_render_collection_filters() {
+        let template = Template::compile(
+            "@for:user in users|where(\"active\")|sort_by(\"name\")|reverse;{@value:user.name;}|@for:(role, members) in users|group_by(\"role\");{@value:role;=@value:members|length; }|@value:tags|unique|join(\",\");",
+        )
+        .unwrap();
+        let modal = json!({"tags": ["x", "y", "x"], "users": [
+            {"name": "Bo", "role": "user", "active": true},
+            {"name": "Al", "role": "admin", "active": true},
+            {"name": "Cy", "role": "user", "active": false}
+        ]});
+
+        assert_eq!(template.render_value(&modal), "BoAl|admin=1 user=2 |x,y");
+    }
+
+    #[test]
+    fn test_render_reuse() {
+        let template = Template::compile("Name: @value:user;").unwrap();
+
+        assert_eq!(template.render_value(&json!({"user": "Bob"})), "Name: Bob");
+        assert_eq!(
+            template.render_value(&json!({"user": "Alice"})),
+            "Name: Alice"
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use super::escape::Escape;
+use super::path::Path;
+use super::render::{Frame, Renderer};
+use super::template::Node;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// Renders a token type registered on an `Engine`, such as `@feature:flag;{...}`.
+///
+/// # Examples
+///
+/// ```
+/// struct Permission;
+///
+/// impl TokenHandler for Permission {
+///     fn render(&self, token: &mut TokenContext) -> Result<(), String> {
+///         let granted = token
+///             .lookup("permissions")
+///             .as_deref()
+///             .and_then(Value::as_array)
+///             .is_some_and(|permissions| permissions.contains(&Value::from(token.key())));
+///
+///         if granted {
+///             token.render_body();
+///         }
+///
+///         Ok(())
+///     }
+/// }
+///
+/// let engine = Engine::new().with_block_token("permission", Permission);
+/// let template = engine.compile("@permission:admin;{<a href=\"/admin\">Admin</a>}")?;
+/// ```
+pub trait TokenHandler: Send + Sync {
+    /// Renders the token, writing to the output through the supplied context.
+    fn render(&self, token: &mut TokenContext) -> Result<(), String>;
+}
+
+impl<F> TokenHandler for F
+where
+    F: Fn(&mut TokenContext) -> Result<(), String> + Send + Sync,
+{
+    fn render(&self, token: &mut TokenContext) -> Result<(), String> {
+        self(token)
+    }
+}
+
+/// A registered token type, and whether it is followed by a `{}` body.
+#[derive(Clone)]
+pub struct CustomToken {
+    pub block: bool,
+    pub handler: Arc<dyn TokenHandler>,
+}
+
+/// What a token handler can see of the template being rendered, and how it writes to the output.
+pub struct TokenContext<'r, 'a> {
+    renderer: &'r mut Renderer<'a>,
+    key: &'r str,
+    escape: Escape,
+    body: &'a [Node],
+}
+
+#[allow(dead_code)]
+impl<'r, 'a> TokenContext<'r, 'a> {
+    pub fn new(
+        renderer: &'r mut Renderer<'a>,
+        key: &'r str,
+        escape: Escape,
+        body: &'a [Node],
+    ) -> TokenContext<'r, 'a> {
+        TokenContext {
+            renderer,
+            key,
+            escape,
+            body,
+        }
+    }
+
+    /// The key of the token, as written in the template.
+    pub fn key(&self) -> &str {
+        self.key
+    }
+
+    /// The modal the template is being rendered against.
+    pub fn modal(&self) -> &Value {
+        self.renderer.modal()
+    }
+
+    /// The items of the for loops the token is inside, outermost first.
+    pub fn loop_stack(&self) -> &[Frame<'a>] {
+        self.renderer.loop_stack()
+    }
+
+    /// Resolves a key the same way a value token would, including loop variables.
+    pub fn lookup(&self, key: &str) -> Option<Cow<'_, Value>> {
+        self.renderer.lookup_path(&Path::parse(key).ok()?)
+    }
+
+    /// Writes a value, escaped for the HTML context the token is in.
+    pub fn write(&mut self, val: &Value) {
+        self.renderer.write_value(self.escape, val);
+    }
+
+    /// Writes markup without escaping. Only for trusted markup.
+    pub fn write_raw(&mut self, markup: &str) {
+        self.renderer.write_value(Escape::Raw, &Value::from(markup));
+    }
+
+    /// Renders the body of a block token. Can be called any number of times, and does nothing for an inline token.
+    pub fn render_body(&mut self) {
+        self.renderer.render_nodes(self.body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::engine::Engine;
+    use super::super::error::{RenderError, TemplateErrorKind};
+    use super::super::template::Template;
+    use super::*;
+    use serde_json::json;
+
+    fn feature_engine() -> Engine {
+        Engine::new()
+            .with_block_token("feature", |token: &mut TokenContext| {
+                let enabled = token
+                    .lookup("features")
+                    .as_deref()
+                    .and_then(|features| features.get(token.key()))
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+
+                if enabled {
+                    token.render_body();
+                }
+
+                Ok(())
+            })
+            .with_token("greet", |token: &mut TokenContext| {
+                let name = token
+                    .lookup(token.key())
+                    .map(Cow::into_owned)
+                    .unwrap_or_default();
+                token.write(&Value::from(format!(
+                    "Hi <{}>",
+                    name.as_str().unwrap_or("?")
+                )));
+                Ok(())
+            })
+    }
+
+    #[test]
+    fn test_token_block() {
+        let engine = feature_engine();
+        let template = engine
+            .compile("@feature:beta;{new}@FEATURE:legacy;{old}")
+            .unwrap();
+        let result = engine.render_value(&template, &json!({"features": {"beta": true}}));
+
+        assert_eq!(result, "new");
+    }
+
+    #[test]
+    fn test_token_inline_escaped() {
+        let engine = feature_engine();
+        let template = engine
+            .compile("@greet:name; @for:user in users;{@greet:user;}")
+            .unwrap();
+        let result = engine.render_value(&template, &json!({"name": "Al", "users": ["Bo"]}));
+
+        assert_eq!(result, "Hi &lt;Al&gt; Hi &lt;Bo&gt;");
+    }
+
+    #[test]
+    fn test_token_loop_stack() {
+        let engine = Engine::new().with_block_token("repeat", |token: &mut TokenContext| {
+            let times = token.key().parse::<usize>().map_err(|_| "bad count")?;
+            let depth = token.loop_stack().len();
+
+            for _ in 0..times * depth {
+                token.render_body();
+            }
+
+            Ok(())
+        });
+        let template = engine
+            .compile("@for:row in rows;{@repeat:2;{@value:row;}}")
+            .unwrap();
+
+        assert_eq!(
+            engine.render_value(&template, &json!({"rows": ["a", "b"]})),
+            "aabb"
+        );
+    }
+
+    #[test]
+    fn test_token_errors() {
+        let engine =
+            Engine::new().with_token("fail", |_token: &mut TokenContext| Err(String::from("no")));
+        let template = engine.compile("@fail:x;").unwrap();
+
+        assert_eq!(
+            engine.try_render_value(&template, &json!({})),
+            Err(RenderError::Token {
+                name: String::from("fail"),
+                message: String::from("no"),
+            })
+        );
+        assert_eq!(
+            Template::compile("@fail:x;").unwrap_err().kind,
+            TemplateErrorKind::UnknownTokenType(String::from("fail"))
+        );
+    }
+}